@@ -0,0 +1,16 @@
+//!
+//! records the rustc version for the environment of the benchmark records
+//!
+
+use std::env;
+use std::process::Command;
+
+fn main() {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let version = Command::new(rustc).arg("--version").output().ok()
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .map(|s| s.trim().to_string())
+        .unwrap_or_default();
+    println!("cargo:rustc-env=BENCH_RUSTC={}", version);
+    println!("cargo:rerun-if-changed=build.rs");
+}
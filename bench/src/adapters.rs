@@ -0,0 +1,104 @@
+//!
+//! SenderI/ReceiverI for what the benchmark drives besides the queue handles:
+//! crossbeam channels and std channels
+//!
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+use queue::{SenderI, ReceiverI, SendError, RecvError, TrySendError, TryRecvError,
+            SendTimeoutError, RecvTimeoutError};
+
+/// crossbeam channel adapted to SenderI/ReceiverI
+#[derive(Clone)]
+pub struct CbSender<T>(pub crossbeam_channel::Sender<T>);
+#[derive(Clone)]
+pub struct CbReceiver<T>(pub crossbeam_channel::Receiver<T>);
+
+impl<T> SenderI<T> for CbSender<T> {
+    fn send(&self, e: T) -> Result<(), SendError<T>> {
+        self.0.send(e).map_err(|e| SendError::Disconnected(e.0))
+    }
+    fn try_send(&self, e: T) -> Result<(), TrySendError<T>> {
+        self.0.try_send(e).map_err(|e| match e {
+            crossbeam_channel::TrySendError::Full(e) => TrySendError::Full(e),
+            crossbeam_channel::TrySendError::Disconnected(e) => TrySendError::Disconnected(e),
+        })
+    }
+    fn send_deadline(&self, e: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        self.0.send_timeout(e, timeout).map_err(|e| match e {
+            crossbeam_channel::SendTimeoutError::Timeout(e) => SendTimeoutError::Timeout(e),
+            crossbeam_channel::SendTimeoutError::Disconnected(e) => SendTimeoutError::Disconnected(e),
+        })
+    }
+}
+impl<T> ReceiverI<T> for CbReceiver<T> {
+    fn recv(&self) -> Result<T, RecvError> {
+        self.0.recv().map_err(|_| RecvError::Disconnected)
+    }
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.0.try_recv().map_err(|e| match e {
+            crossbeam_channel::TryRecvError::Empty => TryRecvError::Empty,
+            crossbeam_channel::TryRecvError::Disconnected => TryRecvError::Disconnected,
+        })
+    }
+    fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        self.0.recv_timeout(timeout).map_err(|e| match e {
+            crossbeam_channel::RecvTimeoutError::Timeout => RecvTimeoutError::Timeout,
+            crossbeam_channel::RecvTimeoutError::Disconnected => RecvTimeoutError::Disconnected,
+        })
+    }
+}
+
+/// std bounded channel adapted to SenderI/ReceiverI, it has one receiver
+#[derive(Clone)]
+pub struct StdSender<T>(pub mpsc::SyncSender<T>);
+pub struct StdReceiver<T>(pub mpsc::Receiver<T>);
+
+impl<T> SenderI<T> for StdSender<T> {
+    fn send(&self, e: T) -> Result<(), SendError<T>> {
+        self.0.send(e).map_err(|e| SendError::Disconnected(e.0))
+    }
+    fn try_send(&self, e: T) -> Result<(), TrySendError<T>> {
+        self.0.try_send(e).map_err(|e| match e {
+            mpsc::TrySendError::Full(e) => TrySendError::Full(e),
+            mpsc::TrySendError::Disconnected(e) => TrySendError::Disconnected(e),
+        })
+    }
+    /// `SyncSender` has no timeout, retry until `deadline`
+    fn send_deadline(&self, mut e: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        loop {
+            match self.try_send(e) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Disconnected(e)) => return Err(SendTimeoutError::Disconnected(e)),
+                Err(TrySendError::Full(full)) if Instant::now() >= deadline => {
+                    return Err(SendTimeoutError::Timeout(full));
+                }
+                Err(TrySendError::Full(full)) => {
+                    e = full;
+                    thread::yield_now();
+                }
+            }
+        }
+    }
+}
+impl<T> ReceiverI<T> for StdReceiver<T> {
+    fn recv(&self) -> Result<T, RecvError> {
+        self.0.recv().map_err(|_| RecvError::Disconnected)
+    }
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.0.try_recv().map_err(|e| match e {
+            mpsc::TryRecvError::Empty => TryRecvError::Empty,
+            mpsc::TryRecvError::Disconnected => TryRecvError::Disconnected,
+        })
+    }
+    fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        self.0.recv_timeout(timeout).map_err(|e| match e {
+            mpsc::RecvTimeoutError::Timeout => RecvTimeoutError::Timeout,
+            mpsc::RecvTimeoutError::Disconnected => RecvTimeoutError::Disconnected,
+        })
+    }
+}
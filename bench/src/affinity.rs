@@ -0,0 +1,173 @@
+//!
+//! pin the benchmark threads to cpus with sched_setaffinity, placed by the
+//! topology in /sys/devices/system/cpu
+//!
+//! Sender i and receiver i get neighbouring places, so with
+//!   siblings: a pair shares a core (hyperthreads, same L1/L2) when there are
+//!             siblings, else neighbouring cores
+//!   socket:   a pair is on different cores of one socket (shared L3)
+//!   spread:   a pair is on different sockets when there are several, each
+//!             thread on its own core as long as there are cores left
+//! More threads than allowed cpus wrap around.
+//!
+
+use std::{fs, io, mem};
+use crate::args::Placement;
+
+/// A cpu and where it is.
+#[derive(Clone, Copy, Debug)]
+struct Cpu {
+    id: usize,
+    package: usize,
+    core: usize,
+    /// rank of the core in its package
+    core_rank: usize,
+    /// rank of the cpu among the siblings of its core
+    thread: usize,
+}
+
+fn read_topology(cpu: usize, name: &str) -> Option<usize> {
+    fs::read_to_string(format!("/sys/devices/system/cpu/cpu{}/topology/{}", cpu, name)).ok()?
+        .trim().parse().ok()
+}
+
+/// The cpus this process may run on.
+fn allowed() -> io::Result<Vec<usize>> {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        if libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((0..libc::CPU_SETSIZE as usize).filter(|&c| libc::CPU_ISSET(c, &set)).collect())
+    }
+}
+
+/// The allowed cpus with their topology. Without topology files (e.g. some
+/// containers) every cpu is a core of package 0.
+fn topology() -> io::Result<Vec<Cpu>> {
+    Ok(allowed()?.into_iter().map(|id| Cpu {
+        id,
+        package: read_topology(id, "physical_package_id").unwrap_or(0),
+        core: read_topology(id, "core_id").unwrap_or(id),
+        core_rank: 0,
+        thread: 0,
+    }).collect())
+}
+
+/// Set `core_rank` and `thread`, the core ids of a package need not be
+/// contiguous.
+fn rank(mut cpus: Vec<Cpu>) -> Vec<Cpu> {
+    cpus.sort_by_key(|c| (c.package, c.core, c.id));
+    for i in 0..cpus.len() {
+        cpus[i].core_rank = 0;
+        cpus[i].thread = 0;
+        if i == 0 {
+            continue;
+        }
+        let (prev, c) = (cpus[i - 1], &mut cpus[i]);
+        if (prev.package, prev.core) == (c.package, c.core) {
+            c.core_rank = prev.core_rank;
+            c.thread = prev.thread + 1;
+        } else if prev.package == c.package {
+            c.core_rank = prev.core_rank + 1;
+        }
+    }
+    cpus
+}
+
+/// The cpus of the senders and the receivers, placed by `p` on `cpus`.
+fn place(p: Placement, cpus: Vec<Cpu>, senders: usize, receivers: usize) -> (Vec<usize>, Vec<usize>) {
+    let mut cpus = rank(cpus);
+    match p {
+        Placement::Siblings => cpus.sort_by_key(|c| (c.package, c.core_rank, c.thread)),
+        Placement::Socket => cpus.sort_by_key(|c| (c.package, c.thread, c.core_rank)),
+        Placement::Spread => cpus.sort_by_key(|c| (c.thread, c.core_rank, c.package)),
+    }
+    // sender 0, receiver 0, sender 1, receiver 1, ... then the rest
+    let (mut s, mut r) = (Vec::new(), Vec::new());
+    let mut next = cpus.iter().map(|c| c.id).cycle();
+    for i in 0..senders.max(receivers) {
+        if i < senders {
+            s.extend(next.next());
+        }
+        if i < receivers {
+            r.extend(next.next());
+        }
+    }
+    (s, r)
+}
+
+/// The cpus of the senders and the receivers.
+pub fn plan(p: Placement, senders: usize, receivers: usize) -> io::Result<(Vec<usize>, Vec<usize>)> {
+    let cpus = topology()?;
+    if cpus.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no allowed cpu"));
+    }
+    Ok(place(p, cpus, senders, receivers))
+}
+
+/// Pin the calling thread to `cpu`.
+pub fn pin(cpu: usize) -> io::Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2 packages of 2 cores of 2 threads, numbered like linux does: the
+    /// first threads of all cores, then the second ones. The core ids of
+    /// package 1 are not contiguous.
+    fn cpus() -> Vec<Cpu> {
+        let cpu = |id, package, core| Cpu { id, package, core, core_rank: 0, thread: 0 };
+        vec![cpu(0, 0, 0), cpu(1, 0, 1), cpu(2, 1, 4), cpu(3, 1, 9),
+             cpu(4, 0, 0), cpu(5, 0, 1), cpu(6, 1, 4), cpu(7, 1, 9)]
+    }
+    fn place_on(p: Placement, s: usize, r: usize) -> (Vec<usize>, Vec<usize>) {
+        place(p, cpus(), s, r)
+    }
+
+    #[test]
+    fn test_rank() {
+        let ranked: Vec<_> = rank(cpus()).iter().map(|c| (c.id, c.core_rank, c.thread)).collect();
+        assert_eq!(ranked, [(0, 0, 0), (4, 0, 1), (1, 1, 0), (5, 1, 1),
+                            (2, 0, 0), (6, 0, 1), (3, 1, 0), (7, 1, 1)]);
+    }
+
+    #[test]
+    fn test_place() {
+        // a pair shares a core
+        assert_eq!(place_on(Placement::Siblings, 1, 1), (vec![0], vec![4]));
+        assert_eq!(place_on(Placement::Siblings, 2, 2), (vec![0, 1], vec![4, 5]));
+        // a pair shares a socket on different cores, then the siblings of the socket
+        assert_eq!(place_on(Placement::Socket, 1, 1), (vec![0], vec![1]));
+        assert_eq!(place_on(Placement::Socket, 3, 3), (vec![0, 4, 2], vec![1, 5, 3]));
+        // a pair is on different sockets, every thread on its own core first
+        assert_eq!(place_on(Placement::Spread, 1, 1), (vec![0], vec![2]));
+        assert_eq!(place_on(Placement::Spread, 2, 2), (vec![0, 1], vec![2, 3]));
+        assert_eq!(place_on(Placement::Spread, 3, 3), (vec![0, 1, 4], vec![2, 3, 6]));
+        // more senders than receivers
+        assert_eq!(place_on(Placement::Spread, 3, 1), (vec![0, 1, 3], vec![2]));
+        assert_eq!(place_on(Placement::Siblings, 1, 2), (vec![0], vec![4, 1]));
+    }
+
+    #[test]
+    fn test_wrap() {
+        // more threads than cpus start over
+        assert_eq!(place_on(Placement::Siblings, 5, 5), (vec![0, 1, 2, 3, 0], vec![4, 5, 6, 7, 4]));
+        assert_eq!(place_on(Placement::Spread, 6, 4), (vec![0, 1, 4, 5, 0, 2], vec![2, 3, 6, 7]));
+
+        // one cpu without siblings
+        let one = vec![Cpu { id: 3, package: 0, core: 3, core_rank: 0, thread: 0 }];
+        for p in [Placement::Siblings, Placement::Socket, Placement::Spread] {
+            assert_eq!(place(p, one.clone(), 2, 1), (vec![3, 3], vec![3]));
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//!
+//! command line of the benchmark
+//!
+
+use std::time::Duration;
+use queue::WaitType;
+
+pub const USAGE: &str = "\
+usage:
+  bench [options]
+options:
+  --queue NAME     spsc2|mpsc|mpmc|lfmpmc|unbounded|rendezvous|crossbeam|std (default lfmpmc)
+  --senders N      sender threads (default 1)
+  --receivers N    receiver threads (default 1)
+  --capacity N     queue capacity (default 131072)
+  --wait MODE      busy|sleep|adaptive|futex, for the queues with wait modes (default sleep)
+  --duration S     send for S seconds (default 10)
+  --messages N     send N messages in total, instead of --duration
+  --pin POLICY     siblings|socket|spread, pin the threads to cpus so that each
+                   sender/receiver pair shares a core, shares a socket, or is
+                   spread apart (linux)
+  --latency        send timestamps and report the send to recv latency
+                   percentiles, at some cost in throughput
+  --format FORMAT  text|json|csv, json and csv print one record (default text)
+  --render [FILE]  print the markdown tables of the json or csv records in the
+                   files, or in stdin, instead of running
+  -h, --help       print this";
+
+/// When the senders stop.
+#[derive(Clone, Copy, Debug)]
+pub enum Limit {
+    Duration(Duration),
+    Messages(u64),
+}
+
+/// Where `--pin` puts the threads, see the affinity module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placement {
+    Siblings,
+    Socket,
+    Spread,
+}
+
+/// How the result is printed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Csv,
+}
+
+#[derive(Clone, Debug)]
+pub struct Args {
+    pub queue: String,
+    pub senders: usize,
+    pub receivers: usize,
+    pub capacity: usize,
+    /// `None` if not given
+    pub wait: Option<WaitType>,
+    pub limit: Limit,
+    /// `None` if not pinned
+    pub pin: Option<Placement>,
+    /// `--latency`
+    pub latency: bool,
+    pub format: Format,
+    /// `--render`: the record files, stdin if empty
+    pub render: Option<Vec<String>>,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            queue: "lfmpmc".to_string(),
+            senders: 1,
+            receivers: 1,
+            capacity: 2 << 16,
+            wait: None,
+            limit: Limit::Duration(Duration::from_secs(10)),
+            pin: None,
+            latency: false,
+            format: Format::Text,
+            render: None,
+        }
+    }
+}
+
+pub fn parse_wait(s: &str) -> Option<WaitType> {
+    match s {
+        "busy" => Some(WaitType::BusyWait),
+        "sleep" => Some(WaitType::SleepWait),
+        "adaptive" => Some(WaitType::adaptive()),
+        #[cfg(target_os = "linux")]
+        "futex" => Some(WaitType::Futex),
+        _ => None,
+    }
+}
+pub fn wait_name(w: WaitType) -> &'static str {
+    match w {
+        WaitType::BusyWait => "busy",
+        WaitType::SleepWait => "sleep",
+        WaitType::Adaptive { .. } => "adaptive",
+        #[cfg(target_os = "linux")]
+        WaitType::Futex => "futex",
+    }
+}
+
+pub fn parse_placement(s: &str) -> Option<Placement> {
+    match s {
+        "siblings" => Some(Placement::Siblings),
+        "socket" => Some(Placement::Socket),
+        "spread" => Some(Placement::Spread),
+        _ => None,
+    }
+}
+pub fn placement_name(p: Placement) -> &'static str {
+    match p {
+        Placement::Siblings => "siblings",
+        Placement::Socket => "socket",
+        Placement::Spread => "spread",
+    }
+}
+
+fn number<T: std::str::FromStr>(name: &str, v: &str) -> Result<T, String> {
+    v.parse().map_err(|_| format!("invalid {}: {}", name, v))
+}
+
+/// Parse the arguments after the program name. `Ok(None)` for `--help`.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Args>, String> {
+    let mut a = Args::default();
+    let mut duration = None;
+    let mut messages = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(None);
+        }
+        if arg == "--latency" {
+            a.latency = true;
+            continue;
+        }
+        if arg == "--render" {
+            a.render = Some(Vec::new());
+            continue;
+        }
+        if let (Some(files), false) = (a.render.as_mut(), arg.starts_with("--")) {
+            files.push(arg);
+            continue;
+        }
+        // --name value or --name=value
+        let (name, value) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+            _ => (arg, None),
+        };
+        let value = match value.or_else(|| args.next()) {
+            Some(v) if name.starts_with("--") => v,
+            _ => return Err(format!("invalid args: {}", name)),
+        };
+        match name.as_str() {
+            "--queue" => a.queue = value,
+            "--senders" => a.senders = number(&name, &value)?,
+            "--receivers" => a.receivers = number(&name, &value)?,
+            "--capacity" => a.capacity = number(&name, &value)?,
+            "--wait" => a.wait = Some(parse_wait(&value).ok_or(format!("invalid --wait: {}", value))?),
+            "--pin" => a.pin = Some(parse_placement(&value).ok_or(format!("invalid --pin: {}", value))?),
+            "--duration" => duration = Some(number::<f64>(&name, &value)?),
+            "--messages" => messages = Some(number(&name, &value)?),
+            "--format" => a.format = match value.as_str() {
+                "text" => Format::Text,
+                "json" => Format::Json,
+                "csv" => Format::Csv,
+                _ => return Err(format!("invalid --format: {}", value)),
+            },
+            _ => return Err(format!("invalid args: {}", name)),
+        }
+    }
+    if a.senders == 0 || a.receivers == 0 || a.capacity == 0 {
+        return Err("--senders, --receivers and --capacity must be at least 1".to_string());
+    }
+    a.limit = match (duration, messages) {
+        (Some(_), Some(_)) => return Err("give --duration or --messages, not both".to_string()),
+        (Some(s), None) if s > 0.0 => Limit::Duration(Duration::from_secs_f64(s)),
+        (Some(s), None) => return Err(format!("invalid --duration: {}", s)),
+        (None, Some(n)) => Limit::Messages(n),
+        (None, None) => a.limit,
+    };
+    Ok(Some(a))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Result<Option<Args>, String> {
+        parse(s.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn test_parse() {
+        let a = args("").unwrap().unwrap();
+        assert_eq!((a.queue.as_str(), a.senders, a.receivers, a.capacity), ("lfmpmc", 1, 1, 2 << 16));
+        assert!(a.wait.is_none() && a.pin.is_none() && !a.latency && a.render.is_none());
+        assert!(matches!(a.limit, Limit::Duration(d) if d == Duration::from_secs(10)));
+
+        // --name value and --name=value
+        let a = args("--queue mpmc --senders=2 --receivers 3 --capacity=8 --wait=adaptive --pin spread \
+                      --latency --format csv --messages=100").unwrap().unwrap();
+        assert_eq!((a.queue.as_str(), a.senders, a.receivers, a.capacity), ("mpmc", 2, 3, 8));
+        assert_eq!(a.wait, Some(WaitType::adaptive()));
+        assert_eq!((a.pin, a.latency, a.format), (Some(Placement::Spread), true, Format::Csv));
+        assert!(matches!(a.limit, Limit::Messages(100)));
+        let a = args("--duration 0.5").unwrap().unwrap();
+        assert!(matches!(a.limit, Limit::Duration(d) if d == Duration::from_millis(500)));
+
+        assert!(args("-h").unwrap().is_none());
+        assert!(args("--queue spsc --help").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(args("--duration 1 --messages 10").unwrap_err(), "give --duration or --messages, not both");
+        assert_eq!(args("--duration 0").unwrap_err(), "invalid --duration: 0");
+        assert_eq!(args("--senders 0").unwrap_err(), "--senders, --receivers and --capacity must be at least 1");
+        assert_eq!(args("--capacity=0").unwrap_err(), "--senders, --receivers and --capacity must be at least 1");
+        assert_eq!(args("--receivers x").unwrap_err(), "invalid --receivers: x");
+        assert_eq!(args("--messages -1").unwrap_err(), "invalid --messages: -1");
+        assert_eq!(args("--wait spin").unwrap_err(), "invalid --wait: spin");
+        assert_eq!(args("--pin far").unwrap_err(), "invalid --pin: far");
+        assert_eq!(args("--format xml").unwrap_err(), "invalid --format: xml");
+        assert_eq!(args("--verbose 1").unwrap_err(), "invalid args: --verbose");
+        assert_eq!(args("mpmc").unwrap_err(), "invalid args: mpmc");
+        // a value is missing
+        assert_eq!(args("--queue").unwrap_err(), "invalid args: --queue");
+    }
+
+    #[test]
+    fn test_render() {
+        let a = args("--render").unwrap().unwrap();
+        assert_eq!(a.render, Some(vec![]));
+        let a = args("--render a.jsonl b.csv --format=json").unwrap().unwrap();
+        assert_eq!(a.render, Some(vec!["a.jsonl".to_string(), "b.csv".to_string()]));
+        assert_eq!(a.format, Format::Json);
+        // files before --render are not files
+        assert!(args("a.jsonl --render").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//!
+//! log-bucketed latency histogram
+//!
+//! Values below `SUB` have a bucket each. Above, every power of two
+//! `[2^k, 2^(k+1))` is split into `SUB` buckets of width `2^(k-SUB_BITS)`,
+//! so a quantile is off by less than 1/`SUB` (6%) of its value.
+//!
+
+const SUB_BITS: u32 = 4;
+const SUB: usize = 1 << SUB_BITS;
+/// enough for all u64 values
+const BUCKETS: usize = (64 - SUB_BITS as usize + 1) * SUB;
+
+#[derive(Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    max: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram { counts: vec![0; BUCKETS], total: 0, max: 0 }
+    }
+
+    fn index(v: u64) -> usize {
+        if v < SUB as u64 {
+            return v as usize;
+        }
+        let k = 63 - v.leading_zeros();
+        let sub = (v >> (k - SUB_BITS)) as usize & (SUB - 1);
+        (k - SUB_BITS + 1) as usize * SUB + sub
+    }
+    /// Highest value of bucket `i`.
+    fn upper(i: usize) -> u64 {
+        if i < SUB {
+            return i as u64;
+        }
+        let shift = (i / SUB) as u32 - 1;
+        let low = ((SUB + i % SUB) as u64) << shift;
+        low + ((1u64 << shift) - 1)
+    }
+
+    #[inline]
+    pub fn record(&mut self, v: u64) {
+        self.counts[Self::index(v)] += 1;
+        self.total += 1;
+        self.max = self.max.max(v);
+    }
+    pub fn merge(&mut self, other: &Histogram) {
+        for (c, o) in self.counts.iter_mut().zip(&other.counts) {
+            *c += o;
+        }
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+    /// Value below which a fraction `q` of the values are, rounded up to its
+    /// bucket. 0 if empty.
+    pub fn quantile(&self, q: f64) -> u64 {
+        if self.total == 0 {
+            return 0;
+        }
+        let rank = ((q * self.total as f64).ceil() as u64).clamp(1, self.total);
+        let mut seen = 0;
+        for (i, c) in self.counts.iter().enumerate() {
+            seen += c;
+            if seen >= rank {
+                return Self::upper(i).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets() {
+        for v in 0..SUB as u64 {
+            assert_eq!(Histogram::index(v), v as usize);
+            assert_eq!(Histogram::upper(v as usize), v);
+        }
+        // every value is in the bucket it maps to, buckets are contiguous
+        for k in SUB_BITS..64 {
+            let p = 1u64 << k;
+            for v in [p - 1, p, p + 1, p.wrapping_mul(2).wrapping_sub(1)] {
+                let i = Histogram::index(v);
+                assert!(i < BUCKETS);
+                assert!(Histogram::upper(i) >= v);
+                assert!(Histogram::upper(i - 1) < v);
+                // above the exact buckets, the width of a bucket is at most 1/SUB of its values
+                if v >= SUB as u64 {
+                    assert!(Histogram::upper(i) - Histogram::upper(i - 1) <= v / SUB as u64);
+                }
+            }
+        }
+        assert_eq!(Histogram::index(u64::MAX), BUCKETS - 1);
+        assert_eq!(Histogram::upper(BUCKETS - 1), u64::MAX);
+        for i in 1..BUCKETS {
+            assert_eq!(Histogram::index(Histogram::upper(i)), i);
+            assert_eq!(Histogram::index(Histogram::upper(i - 1) + 1), i);
+        }
+    }
+
+    #[test]
+    fn test_quantile() {
+        let mut h = Histogram::new();
+        assert_eq!((h.quantile(0.5), h.max()), (0, 0));
+        let values: Vec<u64> = (1..=10000).map(|i| i * 37).collect();
+        for &v in &values {
+            h.record(v);
+        }
+        for &q in &[0.0, 0.1, 0.5, 0.9, 0.99, 0.999, 1.0] {
+            let exact = values[((q * values.len() as f64).ceil() as usize).clamp(1, values.len()) - 1];
+            let got = h.quantile(q);
+            assert!(got >= exact && got - exact <= exact / SUB as u64, "q {}: {} vs {}", q, got, exact);
+        }
+        assert_eq!(h.max(), 370000);
+        assert_eq!(h.quantile(1.0), 370000);
+
+        // clamped to the max, not the top of its bucket
+        let mut h = Histogram::new();
+        h.record(1000);
+        assert_eq!(h.quantile(0.5), 1000);
+        h.record(u64::MAX);
+        assert_eq!(h.quantile(1.0), u64::MAX);
+    }
+
+    #[test]
+    fn test_merge() {
+        let (mut a, mut b, mut all) = (Histogram::new(), Histogram::new(), Histogram::new());
+        for v in 0..5000u64 {
+            let v = v * v;
+            if v % 3 == 0 { a.record(v) } else { b.record(v) }
+            all.record(v);
+        }
+        a.merge(&b);
+        assert_eq!(a.counts, all.counts);
+        assert_eq!((a.total, a.max), (all.total, all.max));
+        for &q in &[0.5, 0.9, 0.99, 0.999] {
+            assert_eq!(a.quantile(q), all.quantile(q));
+        }
+    }
+}
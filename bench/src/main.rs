@@ -0,0 +1,203 @@
+//!
+//! benchmark of the rust queues, crossbeam and std channels, all driven
+//! through SenderI/ReceiverI
+//! usage:
+//!   bench [--queue NAME] [--senders N] [--receivers N] [--capacity N]
+//!         [--wait busy|sleep|adaptive|futex] [--duration S | --messages N]
+//!         [--pin siblings|socket|spread] [--latency] [--format text|json|csv] | --render [FILE]...
+//! e.g.
+//!   bench --queue lfmpmc --senders 2 --receivers 2 --wait adaptive --duration 5
+//!   bench --queue spsc2 --wait busy --messages 100000000
+//!   bench --queue spsc2 --wait busy --pin siblings --duration 5
+//!   bench --queue mpmc --senders 4 --receivers 4 --format json >> results.jsonl
+//!   bench --queue spsc2 --wait futex --latency --format json >> results.jsonl
+//!   bench --render results.jsonl
+//!
+
+mod adapters;
+#[cfg(target_os = "linux")]
+mod affinity;
+mod args;
+mod histogram;
+mod report;
+mod run;
+
+use std::io::{self, Read};
+use std::{fs, process};
+use queue::WaitType;
+use queue::spsc::new_spsc;
+use queue::mpsc::new_mpsc;
+use queue::mpmc::new_mpmc;
+use queue::lfmpmc::new_lfmpmc;
+use queue::unbounded::new_unbounded;
+use queue::rendezvous::new_rendezvous;
+use adapters::{CbSender, CbReceiver, StdSender, StdReceiver};
+use args::{Args, Format, Placement, wait_name, placement_name};
+use report::{Record, Env, Latency};
+use run::{run, Config, Outcome};
+
+/// The most senders and receivers a queue takes, 1 or any, and whether it
+/// has wait modes.
+fn limits(queue: &str) -> Option<(usize, usize, bool)> {
+    const ANY: usize = usize::MAX;
+    Some(match queue {
+        "spsc2" => (1, 1, true),
+        "mpsc" => (ANY, 1, true),
+        "mpmc" | "unbounded" | "crossbeam" => (ANY, ANY, false),
+        "lfmpmc" | "rendezvous" => (ANY, ANY, true),
+        "std" => (ANY, 1, false),
+        _ => return None,
+    })
+}
+
+fn clones<H: Clone>(h: H, n: usize) -> Vec<H> {
+    vec![h; n]
+}
+
+fn bench(a: &Args, wait: WaitType, c: &Config) -> Outcome {
+    let (ns, nr, cap) = (a.senders, a.receivers, a.capacity);
+    match a.queue.as_str() {
+        "spsc2" => {
+            let (s, r) = new_spsc::<i64>(cap, wait);
+            run(vec![s], vec![r], c)
+        }
+        "mpsc" => {
+            let (s, r) = new_mpsc::<i64>(cap, wait);
+            run(clones(s, ns), vec![r], c)
+        }
+        "mpmc" => {
+            let (s, r) = new_mpmc::<i64>(cap);
+            run(clones(s, ns), clones(r, nr), c)
+        }
+        "lfmpmc" => {
+            let (s, r) = new_lfmpmc::<i64>(cap, wait);
+            run(clones(s, ns), clones(r, nr), c)
+        }
+        "unbounded" => {
+            let (s, r) = new_unbounded::<i64>();
+            run(clones(s, ns), clones(r, nr), c)
+        }
+        "rendezvous" => {
+            let (s, r) = new_rendezvous::<i64>(wait);
+            run(clones(s, ns), clones(r, nr), c)
+        }
+        "crossbeam" => {
+            let (s, r) = crossbeam_channel::bounded::<i64>(cap);
+            run(clones(CbSender(s), ns), clones(CbReceiver(r), nr), c)
+        }
+        "std" => {
+            let (s, r) = std::sync::mpsc::sync_channel::<i64>(cap);
+            run(clones(StdSender(s), ns), vec![StdReceiver(r)], c)
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// The cpus of the senders and receivers for `--pin`.
+#[cfg(target_os = "linux")]
+fn plan(p: Placement, senders: usize, receivers: usize) -> Result<(Vec<usize>, Vec<usize>), String> {
+    affinity::plan(p, senders, receivers).map_err(|e| format!("--pin: {}", e))
+}
+#[cfg(not(target_os = "linux"))]
+fn plan(_p: Placement, _senders: usize, _receivers: usize) -> Result<(Vec<usize>, Vec<usize>), String> {
+    Err("--pin needs linux".to_string())
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}\n{}", msg, args::USAGE);
+    process::exit(2);
+}
+
+/// `--render`: read the records and print the tables.
+fn render(files: &[String]) -> Result<(), String> {
+    let mut text = String::new();
+    if files.is_empty() {
+        io::stdin().read_to_string(&mut text).map_err(|e| e.to_string())?;
+    }
+    for f in files {
+        text += &fs::read_to_string(f).map_err(|e| format!("{}: {}", f, e))?;
+        text.push('\n');
+    }
+    let records = report::parse(&text)?;
+    print!("{}", report::render(&records));
+    Ok(())
+}
+
+fn main() {
+    let a = match args::parse(std::env::args().skip(1)) {
+        Ok(Some(a)) => a,
+        Ok(None) => {
+            println!("{}", args::USAGE);
+            return;
+        }
+        Err(e) => fail(&e),
+    };
+    if let Some(files) = &a.render {
+        if let Err(e) = render(files) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    let (max_send, max_recv, waits) = match limits(&a.queue) {
+        Some(l) => l,
+        None => fail(&format!("invalid --queue: {}", a.queue)),
+    };
+    if a.senders > max_send {
+        fail(&format!("{} takes one sender", a.queue));
+    }
+    if a.receivers > max_recv {
+        fail(&format!("{} takes one receiver", a.queue));
+    }
+    if a.wait.is_some() && !waits {
+        fail(&format!("{} has no wait modes", a.queue));
+    }
+    let wait = a.wait.unwrap_or(WaitType::SleepWait);
+    if a.queue == "rendezvous" && wait_name(wait) == "futex" {
+        fail("rendezvous has no futex wait mode");
+    }
+    let wait_label = if waits { wait_name(wait) } else { "-" };
+    let (send_cpus, recv_cpus) = match a.pin {
+        Some(p) => plan(p, a.senders, a.receivers).unwrap_or_else(|e| fail(&e)),
+        None => (Vec::new(), Vec::new()),
+    };
+    let c = Config { limit: a.limit, latency: a.latency, send_cpus, recv_cpus };
+
+    if a.format == Format::Text {
+        println!("======bench rust {}(capacity {}, wait {}): {} sender, {} receiver======",
+                 a.queue, a.capacity, wait_label, a.senders, a.receivers);
+        if let Some(p) = a.pin {
+            println!("pin {}: senders on cpus {:?}, receivers on cpus {:?}",
+                     placement_name(p), c.send_cpus, c.recv_cpus);
+        }
+    }
+    let o = bench(&a, wait, &c);
+    let record = Record {
+        queue: a.queue.clone(),
+        senders: a.senders,
+        receivers: a.receivers,
+        capacity: a.capacity,
+        wait: wait_label.to_string(),
+        pin: a.pin.map_or("-", placement_name).to_string(),
+        messages: o.received,
+        elapsed_ms: o.elapsed.as_secs_f64() * 1e3,
+        recv_per_ms: o.per_ms(o.received),
+        ns_per_recv: o.ns_per(o.received),
+        latency: o.latency.as_ref().map(Latency::of),
+        env: Env::current(),
+    };
+    match a.format {
+        Format::Text => {
+            println!("send: {} in {} ms, {:.0} send/ms, {:.0} ns/send",
+                     o.sent, o.elapsed.as_millis(), o.per_ms(o.sent), o.ns_per(o.sent));
+            println!("recv: {} in {} ms, {:.0} recv/ms, {:.0} ns/recv",
+                     o.received, o.elapsed.as_millis(), record.recv_per_ms, record.ns_per_recv);
+            if let Some(l) = &record.latency {
+                println!("latency: p50 {} ns, p90 {} ns, p99 {} ns, p99.9 {} ns, max {} ns",
+                         l.p50, l.p90, l.p99, l.p999, l.max);
+            }
+        }
+        Format::Json => println!("{}", record.to_json()),
+        Format::Csv => println!("{}\n{}", report::CSV_HEADER, record.to_csv()),
+    }
+}
@@ -0,0 +1,627 @@
+//!
+//! benchmark records as JSON lines or CSV, and the markdown tables of the
+//! READMEs rendered from them
+//!
+//! A record is a flat object, one per run:
+//!   {"queue":"lfmpmc","senders":1,"receivers":1,"capacity":131072,"wait":"sleep","pin":"-",
+//!    "messages":...,"elapsed_ms":...,"recv_per_ms":...,"ns_per_recv":...,
+//!    "p50_ns":...,"p90_ns":...,"p99_ns":...,"p999_ns":...,"max_ns":...,
+//!    "cpu":"...","cpus":4,"os":"linux x86_64","kernel":"...","rustc":"rustc 1.x"}
+//! The CSV has the same columns in the same order, with a header line.
+//! The latencies are null (empty in CSV) unless the run was `--latency`.
+//!
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use crate::histogram::Histogram;
+
+/// Where a benchmark ran.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Env {
+    pub cpu: String,
+    pub cpus: usize,
+    /// os and architecture
+    pub os: String,
+    pub kernel: String,
+    pub rustc: String,
+}
+
+impl Env {
+    pub fn current() -> Env {
+        let cpu = fs::read_to_string("/proc/cpuinfo").ok()
+            .and_then(|s| s.lines().find(|l| l.starts_with("model name"))
+                .and_then(|l| l.split(':').nth(1)).map(|m| m.trim().to_string()))
+            .unwrap_or_else(|| "unknown".to_string());
+        Env {
+            cpu,
+            cpus: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            os: format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+            kernel: fs::read_to_string("/proc/sys/kernel/osrelease")
+                .map(|s| s.trim().to_string()).unwrap_or_default(),
+            rustc: env!("BENCH_RUSTC").to_string(),
+        }
+    }
+}
+
+/// Percentiles of the send to recv latency, in ns.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Latency {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub p999: u64,
+    pub max: u64,
+}
+
+impl Latency {
+    pub fn of(h: &Histogram) -> Latency {
+        Latency {
+            p50: h.quantile(0.5),
+            p90: h.quantile(0.9),
+            p99: h.quantile(0.99),
+            p999: h.quantile(0.999),
+            max: h.max(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub queue: String,
+    pub senders: usize,
+    pub receivers: usize,
+    pub capacity: usize,
+    /// `-` for the queues without wait modes
+    pub wait: String,
+    /// `--pin` placement, `-` if not pinned
+    pub pin: String,
+    /// messages received
+    pub messages: u64,
+    pub elapsed_ms: f64,
+    pub recv_per_ms: f64,
+    pub ns_per_recv: f64,
+    /// `None` unless the run was `--latency`
+    pub latency: Option<Latency>,
+    pub env: Env,
+}
+
+/// A field of a record, in the JSON and CSV order.
+enum Value {
+    Str(String),
+    Num(String),
+    Null,
+}
+
+pub const CSV_HEADER: &str = "queue,senders,receivers,capacity,wait,pin,messages,elapsed_ms,\
+                              recv_per_ms,ns_per_recv,p50_ns,p90_ns,p99_ns,p999_ns,max_ns,cpu,cpus,os,kernel,rustc";
+
+impl Record {
+    fn fields(&self) -> Vec<(&'static str, Value)> {
+        use Value::*;
+        let lat = |f: fn(&Latency) -> u64| self.latency.as_ref().map_or(Null, |l| Num(f(l).to_string()));
+        vec![
+            ("queue", Str(self.queue.clone())),
+            ("senders", Num(self.senders.to_string())),
+            ("receivers", Num(self.receivers.to_string())),
+            ("capacity", Num(self.capacity.to_string())),
+            ("wait", Str(self.wait.clone())),
+            ("pin", Str(self.pin.clone())),
+            ("messages", Num(self.messages.to_string())),
+            ("elapsed_ms", Num(format!("{:.3}", self.elapsed_ms))),
+            ("recv_per_ms", Num(format!("{:.1}", self.recv_per_ms))),
+            ("ns_per_recv", Num(format!("{:.1}", self.ns_per_recv))),
+            ("p50_ns", lat(|l| l.p50)),
+            ("p90_ns", lat(|l| l.p90)),
+            ("p99_ns", lat(|l| l.p99)),
+            ("p999_ns", lat(|l| l.p999)),
+            ("max_ns", lat(|l| l.max)),
+            ("cpu", Str(self.env.cpu.clone())),
+            ("cpus", Num(self.env.cpus.to_string())),
+            ("os", Str(self.env.os.clone())),
+            ("kernel", Str(self.env.kernel.clone())),
+            ("rustc", Str(self.env.rustc.clone())),
+        ]
+    }
+    /// Build from the fields by name, the inverse of `fields`.
+    fn from_fields(mut f: BTreeMap<String, String>) -> Result<Record, String> {
+        let mut take = |k: &str| f.remove(k).ok_or(format!("record without {}", k));
+        fn num<T: std::str::FromStr>(k: &str, v: String) -> Result<T, String> {
+            v.parse().map_err(|_| format!("invalid {}: {}", k, v))
+        }
+        // null, empty or missing in the records of throughput runs
+        let mut lat = |k: &str| match take(k) {
+            Ok(v) if v != "null" && !v.is_empty() => num(k, v).map(Some),
+            _ => Ok(None),
+        };
+        let latency = match (lat("p50_ns")?, lat("p90_ns")?, lat("p99_ns")?, lat("p999_ns")?, lat("max_ns")?) {
+            (Some(p50), Some(p90), Some(p99), Some(p999), Some(max)) => Some(Latency { p50, p90, p99, p999, max }),
+            (None, None, None, None, None) => None,
+            _ => return Err("record with some of the latencies".to_string()),
+        };
+        Ok(Record {
+            queue: take("queue")?,
+            senders: num("senders", take("senders")?)?,
+            receivers: num("receivers", take("receivers")?)?,
+            capacity: num("capacity", take("capacity")?)?,
+            wait: take("wait")?,
+            // not in the records from before --pin
+            pin: take("pin").unwrap_or_else(|_| "-".to_string()),
+            messages: num("messages", take("messages")?)?,
+            elapsed_ms: num("elapsed_ms", take("elapsed_ms")?)?,
+            recv_per_ms: num("recv_per_ms", take("recv_per_ms")?)?,
+            ns_per_recv: num("ns_per_recv", take("ns_per_recv")?)?,
+            latency,
+            env: Env {
+                cpu: take("cpu")?,
+                cpus: num("cpus", take("cpus")?)?,
+                os: take("os")?,
+                kernel: take("kernel")?,
+                rustc: take("rustc")?,
+            },
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        let fields: Vec<String> = self.fields().into_iter().map(|(k, v)| match v {
+            Value::Str(s) => format!("\"{}\":{}", k, json_string(&s)),
+            Value::Num(n) => format!("\"{}\":{}", k, n),
+            Value::Null => format!("\"{}\":null", k),
+        }).collect();
+        format!("{{{}}}", fields.join(","))
+    }
+    pub fn to_csv(&self) -> String {
+        let fields: Vec<String> = self.fields().into_iter().map(|(_, v)| match v {
+            Value::Str(s) => csv_field(&s),
+            Value::Num(n) => n,
+            Value::Null => String::new(),
+        }).collect();
+        fields.join(",")
+    }
+
+    /// Parse a line written by `to_json`.
+    pub fn from_json(line: &str) -> Result<Record, String> {
+        let mut p = JsonParser { s: line.trim().as_bytes(), i: 0 };
+        let mut f = BTreeMap::new();
+        p.expect(b'{')?;
+        if !p.eat(b'}') {
+            loop {
+                let k = p.string()?;
+                p.expect(b':')?;
+                let v = if p.peek() == Some(b'"') { p.string()? } else { p.number() };
+                f.insert(k, v);
+                if p.eat(b'}') {
+                    break;
+                }
+                p.expect(b',')?;
+            }
+        }
+        Record::from_fields(f)
+    }
+    /// Parse a line written by `to_csv`, with the columns of `header`, e.g.
+    /// `CSV_HEADER`.
+    pub fn from_csv(header: &str, line: &str) -> Result<Record, String> {
+        let names = parse_csv(header)?;
+        let values = parse_csv(line)?;
+        if values.len() != names.len() {
+            return Err(format!("invalid csv record: {}", line));
+        }
+        Record::from_fields(names.into_iter().zip(values).collect())
+    }
+
+    /// Column of the record in the tables, e.g. `spsc2(busy)@siblings`.
+    fn implementation(&self) -> String {
+        let mut s = self.queue.clone();
+        if self.wait != "-" {
+            s += &format!("({})", self.wait);
+        }
+        if self.pin != "-" {
+            s += &format!("@{}", self.pin);
+        }
+        s
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Just enough JSON for the flat objects of `to_json`.
+struct JsonParser<'a> {
+    s: &'a [u8],
+    i: usize,
+}
+impl JsonParser<'_> {
+    fn skip_ws(&mut self) {
+        while self.i < self.s.len() && self.s[self.i].is_ascii_whitespace() {
+            self.i += 1;
+        }
+    }
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_ws();
+        self.s.get(self.i).copied()
+    }
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.i += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        if self.eat(c) { Ok(()) } else { Err(format!("expected '{}' at {}", c as char, self.i)) }
+    }
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut out = Vec::new();
+        while let Some(&c) = self.s.get(self.i) {
+            self.i += 1;
+            match c {
+                b'"' => return String::from_utf8(out).map_err(|e| e.to_string()),
+                b'\\' => {
+                    let e = *self.s.get(self.i).ok_or("unterminated string")?;
+                    self.i += 1;
+                    match e {
+                        b'n' => out.push(b'\n'),
+                        b't' => out.push(b'\t'),
+                        b'u' => {
+                            let hex = self.s.get(self.i..self.i + 4).ok_or("invalid \\u")?;
+                            self.i += 4;
+                            let code = std::str::from_utf8(hex).ok()
+                                .and_then(|h| u32::from_str_radix(h, 16).ok())
+                                .and_then(char::from_u32).ok_or("invalid \\u")?;
+                            let mut buf = [0; 4];
+                            out.extend_from_slice(code.encode_utf8(&mut buf).as_bytes());
+                        }
+                        e => out.push(e),
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+        Err("unterminated string".to_string())
+    }
+    fn number(&mut self) -> String {
+        self.skip_ws();
+        let start = self.i;
+        while self.i < self.s.len() && !matches!(self.s[self.i], b',' | b'}') && !self.s[self.i].is_ascii_whitespace() {
+            self.i += 1;
+        }
+        String::from_utf8_lossy(&self.s[start..self.i]).into_owned()
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+fn parse_csv(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut chars = line.trim_end_matches(['\r', '\n']).chars().peekable();
+    loop {
+        let mut f = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        f.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => f.push(c),
+                    None => return Err(format!("unterminated csv field: {}", line)),
+                }
+            }
+        }
+        while let Some(&c) = chars.peek() {
+            if c == ',' {
+                break;
+            }
+            f.push(c);
+            chars.next();
+        }
+        fields.push(f);
+        if chars.next().is_none() {
+            return Ok(fields);
+        }
+    }
+}
+
+/// Read records from JSON lines or CSV, e.g. the output of several runs
+/// appended to one file. Blank lines are skipped. A CSV line has the columns
+/// of the last header, so files of older versions of the bench still parse.
+pub fn parse(text: &str) -> Result<Vec<Record>, String> {
+    let mut header = CSV_HEADER;
+    let mut records = Vec::new();
+    for l in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if l.starts_with('{') {
+            records.push(Record::from_json(l)?);
+        } else if l.starts_with("queue,") {
+            header = l;
+        } else {
+            records.push(Record::from_csv(header, l)?);
+        }
+    }
+    Ok(records)
+}
+
+/// A table: its unit and the value of a cell from its mean recv/ms.
+type Unit = (&'static str, fn(f64) -> f64);
+/// The runs of a table: (senders, receivers) -> column -> runs.
+type Cells<'a> = BTreeMap<(usize, usize), BTreeMap<String, Vec<&'a Record>>>;
+
+/// Markdown tables like in the READMEs: per environment, its description,
+/// then per capacity one row per sender/receiver count and one column per
+/// queue and wait mode, in recv/ms and in ns/recv. Repeated runs of a cell
+/// are averaged in recv/ms and its ns/recv is derived from that mean, runs
+/// of different environments or capacities never are.
+/// The `--latency` runs, slowed down by their timestamps, are left out of
+/// those and get a table of their percentiles, one row per run.
+pub fn render(records: &[Record]) -> String {
+    let mut envs: Vec<&Env> = Vec::new();
+    for r in records {
+        if !envs.contains(&&r.env) {
+            envs.push(&r.env);
+        }
+    }
+    let blocks: Vec<String> = envs.iter().map(|e| {
+        let runs: Vec<&Record> = records.iter().filter(|r| &r.env == *e).collect();
+        render_env(e, &runs)
+    }).collect();
+    blocks.join("\n")
+}
+
+/// The tables of the records of one environment.
+fn render_env(env: &Env, records: &[&Record]) -> String {
+    let mut columns: Vec<String> = Vec::new();
+    let mut tables: BTreeMap<usize, Cells> = BTreeMap::new();
+    for r in records.iter().filter(|r| r.latency.is_none()) {
+        let col = r.implementation();
+        if !columns.contains(&col) {
+            columns.push(col.clone());
+        }
+        tables.entry(r.capacity).or_default()
+            .entry((r.senders, r.receivers)).or_default()
+            .entry(col).or_default().push(r);
+    }
+
+    let mut out = String::new();
+    out.push_str("** env **\n");
+    let _ = writeln!(out, "* {}, {} cpus", env.cpu, env.cpus);
+    let _ = writeln!(out, "* {} {}", env.os, env.kernel);
+    let _ = writeln!(out, "* {}", env.rustc);
+    let units: [Unit; 2] = [("recv/ms", |per_ms| per_ms), ("ns/recv", |per_ms| 1e6 / per_ms)];
+    for (capacity, cells) in &tables {
+        // the columns with runs of this capacity
+        let columns: Vec<&String> = columns.iter()
+            .filter(|c| cells.values().any(|row| row.contains_key(*c)))
+            .collect();
+        for (unit, value) in units.iter() {
+            let _ = write!(out, "\nunit: {}, capacity {}\n\nsender | receiver", unit, capacity);
+            for c in &columns {
+                let _ = write!(out, " | {}", c);
+            }
+            out.push_str("\n-------|----------");
+            for c in &columns {
+                let _ = write!(out, "|{}", "-".repeat(c.len() + 2));
+            }
+            out.push('\n');
+            for ((s, r), row) in cells {
+                let _ = write!(out, "{:<6} | {:<8}", s, r);
+                for c in &columns {
+                    match row.get(*c) {
+                        Some(runs) => {
+                            let per_ms = runs.iter().map(|r| r.recv_per_ms).sum::<f64>() / runs.len() as f64;
+                            let _ = write!(out, " | {:.0}", value(per_ms));
+                        }
+                        None => out.push_str(" |"),
+                    }
+                }
+                out.push('\n');
+            }
+        }
+    }
+    let latencies: Vec<_> = records.iter().filter_map(|r| r.latency.map(|l| (r, l))).collect();
+    if !latencies.is_empty() {
+        out.push_str("\nunit: ns latency\n\n");
+        out.push_str("sender | receiver | capacity | queue | p50 | p90 | p99 | p99.9 | max\n");
+        out.push_str("-------|----------|----------|-------|-----|-----|-----|-------|-----\n");
+        for (r, l) in latencies {
+            let _ = writeln!(out, "{:<6} | {:<8} | {:<8} | {} | {} | {} | {} | {} | {}",
+                             r.senders, r.receivers, r.capacity, r.implementation(),
+                             l.p50, l.p90, l.p99, l.p999, l.max);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(cpu: &str) -> Env {
+        Env { cpu: cpu.to_string(), cpus: 4, os: "linux x86_64".to_string(),
+              kernel: "6.1".to_string(), rustc: "rustc 1.70.0".to_string() }
+    }
+    fn record(queue: &str, wait: &str, senders: usize, recv_per_ms: f64, env: Env) -> Record {
+        Record {
+            queue: queue.to_string(),
+            senders,
+            receivers: 1,
+            capacity: 16,
+            wait: wait.to_string(),
+            pin: "-".to_string(),
+            messages: 1000,
+            elapsed_ms: 2.5,
+            recv_per_ms,
+            ns_per_recv: 1e6 / recv_per_ms,
+            latency: None,
+            env,
+        }
+    }
+
+    #[test]
+    fn test_json() {
+        let mut r = record("spsc2", "busy", 1, 400.0, env("cpu \"x\", 2\\3\n\tGHz"));
+        r.pin = "siblings".to_string();
+        r.latency = Some(Latency { p50: 100, p90: 200, p99: 300, p999: 400, max: 5000 });
+        let json = r.to_json();
+        assert!(json.contains(r#""cpu":"cpu \"x\", 2\\3\n\u0009GHz""#));
+        assert_eq!(Record::from_json(&json), Ok(r.clone()));
+
+        r.latency = None;
+        let json = r.to_json();
+        assert!(json.contains(r#""p50_ns":null,"#));
+        assert_eq!(Record::from_json(&json), Ok(r));
+
+        assert!(Record::from_json(r#"{"queue":"spsc"}"#).unwrap_err().contains("record without"));
+        assert!(Record::from_json(r#"{"queue":"spsc""#).is_err());
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut r = record("mpmc", "-", 2, 250.0, env("cpu \"x\", 2\\3\nGHz"));
+        r.latency = Some(Latency { p50: 1, p90: 2, p99: 3, p999: 4, max: 5 });
+        let csv = r.to_csv();
+        assert!(csv.contains(r#","cpu ""x"", 2\3"#));
+        assert_eq!(Record::from_csv(CSV_HEADER, &csv), Ok(r.clone()));
+
+        r.latency = None;
+        let csv = r.to_csv();
+        assert!(csv.contains(",,,,,"));
+        assert_eq!(Record::from_csv(CSV_HEADER, &csv), Ok(r));
+
+        assert!(Record::from_csv(CSV_HEADER, "mpmc,1").is_err());
+        assert!(Record::from_csv(CSV_HEADER, "\"mpmc").is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let r = record("lfmpmc", "sleep", 1, 500.0, env("cpu"));
+        // the header before --pin and --latency
+        let old = "queue,senders,receivers,capacity,wait,messages,elapsed_ms,recv_per_ms,ns_per_recv,\
+                   cpu,cpus,os,kernel,rustc\n\
+                   lfmpmc,1,1,16,sleep,1000,2.500,500.0,2000.0,cpu,4,linux x86_64,6.1,rustc 1.70.0\n";
+        let text = format!("{}\n{}\n\n{}\n{}\n{}", r.to_json(), old, CSV_HEADER, r.to_csv(), r.to_json());
+        assert_eq!(parse(&text), Ok(vec![r.clone(), r.clone(), r.clone(), r]));
+        assert!(parse("lfmpmc,1").is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let (a, b) = (env("cpu a"), env("cpu b"));
+        let mut lat = record("spsc2", "futex", 1, 100.0, a.clone());
+        lat.latency = Some(Latency { p50: 10, p90: 20, p99: 30, p999: 40, max: 50 });
+        let mut pinned = record("spsc2", "busy", 1, 800.0, a.clone());
+        pinned.pin = "siblings".to_string();
+        let records = vec![
+            record("mpmc", "-", 1, 400.0, a.clone()),
+            record("mpmc", "-", 1, 600.0, a.clone()),
+            record("lfmpmc", "sleep", 2, 1000.0, a.clone()),
+            pinned,
+            lat,
+            // another machine, not averaged with the above
+            record("mpmc", "-", 1, 50.0, b),
+        ];
+        let expected = "\
+** env **
+* cpu a, 4 cpus
+* linux x86_64 6.1
+* rustc 1.70.0
+
+unit: recv/ms, capacity 16
+
+sender | receiver | mpmc | lfmpmc(sleep) | spsc2(busy)@siblings
+-------|----------|------|---------------|----------------------
+1      | 1        | 500 | | 800
+2      | 1        | | 1000 |
+
+unit: ns/recv, capacity 16
+
+sender | receiver | mpmc | lfmpmc(sleep) | spsc2(busy)@siblings
+-------|----------|------|---------------|----------------------
+1      | 1        | 2000 | | 1250
+2      | 1        | | 1000 |
+
+unit: ns latency
+
+sender | receiver | capacity | queue | p50 | p90 | p99 | p99.9 | max
+-------|----------|----------|-------|-----|-----|-----|-------|-----
+1      | 1        | 16       | spsc2(futex) | 10 | 20 | 30 | 40 | 50
+
+** env **
+* cpu b, 4 cpus
+* linux x86_64 6.1
+* rustc 1.70.0
+
+unit: recv/ms, capacity 16
+
+sender | receiver | mpmc
+-------|----------|------
+1      | 1        | 50
+
+unit: ns/recv, capacity 16
+
+sender | receiver | mpmc
+-------|----------|------
+1      | 1        | 20000
+";
+        assert_eq!(render(&records), expected);
+    }
+
+    #[test]
+    fn test_render_capacity() {
+        // the same cell but for the capacity: a table each, never averaged
+        let mut big = record("mpmc", "-", 1, 800.0, env("cpu"));
+        big.capacity = 1024;
+        let records = vec![big, record("mpmc", "-", 1, 400.0, env("cpu")),
+                           record("lfmpmc", "sleep", 1, 200.0, env("cpu"))];
+        let expected = "\
+** env **
+* cpu, 4 cpus
+* linux x86_64 6.1
+* rustc 1.70.0
+
+unit: recv/ms, capacity 16
+
+sender | receiver | mpmc | lfmpmc(sleep)
+-------|----------|------|---------------
+1      | 1        | 400 | 200
+
+unit: ns/recv, capacity 16
+
+sender | receiver | mpmc | lfmpmc(sleep)
+-------|----------|------|---------------
+1      | 1        | 2500 | 5000
+
+unit: recv/ms, capacity 1024
+
+sender | receiver | mpmc
+-------|----------|------
+1      | 1        | 800
+
+unit: ns/recv, capacity 1024
+
+sender | receiver | mpmc
+-------|----------|------
+1      | 1        | 1250
+";
+        assert_eq!(render(&records), expected);
+    }
+}
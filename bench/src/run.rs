@@ -0,0 +1,133 @@
+//!
+//! drive senders and receivers through SenderI/ReceiverI
+//!
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use queue::{SenderI, ReceiverI};
+use crate::args::Limit;
+use crate::histogram::Histogram;
+
+/// Tells a receiver to stop. The last sender sends one to each receiver,
+/// so that they all stop the same way, whether or not the queue disconnects.
+const STOP: i64 = -1;
+
+/// How `run` runs.
+pub struct Config {
+    pub limit: Limit,
+    /// see `run`
+    pub latency: bool,
+    /// cpu of each sender and receiver, empty if not pinned
+    pub send_cpus: Vec<usize>,
+    pub recv_cpus: Vec<usize>,
+}
+
+pub struct Outcome {
+    pub sent: u64,
+    pub received: u64,
+    /// from starting the threads until the last receiver stopped
+    pub elapsed: Duration,
+    /// latency mode: the send to recv latencies in ns of all the receivers
+    pub latency: Option<Histogram>,
+}
+
+impl Outcome {
+    pub fn per_ms(&self, n: u64) -> f64 {
+        n as f64 / (self.elapsed.as_nanos() as f64 / 1e6)
+    }
+    pub fn ns_per(&self, n: u64) -> f64 {
+        self.elapsed.as_nanos() as f64 / n.max(1) as f64
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn pin(cpu: Option<usize>) {
+    if let Some(cpu) = cpu {
+        crate::affinity::pin(cpu).expect("sched_setaffinity");
+    }
+}
+#[cfg(not(target_os = "linux"))]
+fn pin(_cpu: Option<usize>) {}
+
+/// One thread per handle, on its cpu if pinned. The senders send until
+/// `limit`, the receivers until they get `STOP`.
+/// With `latency`, the senders send the ns since the start, read just before
+/// `send` (so waiting for room counts), instead of a counter, and the
+/// receivers record how long ago that was.
+pub fn run<S, R>(senders: Vec<S>, receivers: Vec<R>, c: &Config) -> Outcome
+    where S: SenderI<i64> + Send + 'static,
+          R: ReceiverI<i64> + Send + 'static {
+    let n_send = senders.len() as u64;
+    let n_recv = receivers.len();
+    let stop = Arc::new(AtomicBool::new(false));
+    let running = Arc::new(AtomicUsize::new(senders.len()));
+    let (limit, latency) = (c.limit, c.latency);
+    let begin = Instant::now();
+
+    let recv_threads: Vec<_> = receivers.into_iter().enumerate().map(|(k, r)| {
+        let cpu = c.recv_cpus.get(k).copied();
+        thread::spawn(move || {
+            pin(cpu);
+            let mut n = 0u64;
+            let mut hist = if latency { Some(Histogram::new()) } else { None };
+            while let Ok(e) = r.recv() {
+                if e == STOP {
+                    break;
+                }
+                if let Some(h) = hist.as_mut() {
+                    h.record((begin.elapsed().as_nanos() as i64 - e).max(0) as u64);
+                }
+                n += 1;
+            }
+            (n, hist)
+        })
+    }).collect();
+
+    let send_threads: Vec<_> = senders.into_iter().enumerate().map(|(k, s)| {
+        let stop = stop.clone();
+        let running = running.clone();
+        // the messages are spread over the senders
+        let quota = match limit {
+            Limit::Messages(m) => m / n_send + ((k as u64) < m % n_send) as u64,
+            Limit::Duration(_) => u64::MAX,
+        };
+        let cpu = c.send_cpus.get(k).copied();
+        thread::spawn(move || {
+            pin(cpu);
+            let mut n = 0u64;
+            while n < quota && !stop.load(Ordering::Relaxed) {
+                let e = if latency { begin.elapsed().as_nanos() as i64 } else { n as i64 };
+                if s.send(e).is_err() {
+                    break;
+                }
+                n += 1;
+            }
+            if running.fetch_sub(1, Ordering::SeqCst) == 1 {
+                for _ in 0..n_recv {
+                    let _ = s.send(STOP);
+                }
+            }
+            n
+        })
+    }).collect();
+
+    if let Limit::Duration(d) = limit {
+        thread::sleep(d);
+        stop.store(true, Ordering::Relaxed);
+    }
+    let sent = send_threads.into_iter().map(|t| t.join().unwrap()).sum();
+    let mut received = 0;
+    let mut hist: Option<Histogram> = None;
+    for t in recv_threads {
+        let (n, h) = t.join().unwrap();
+        received += n;
+        match (hist.as_mut(), h) {
+            (Some(all), Some(h)) => all.merge(&h),
+            (None, h) => hist = h,
+            _ => {}
+        }
+    }
+    Outcome { sent, received, elapsed: begin.elapsed(), latency: hist }
+}
@@ -1,20 +1,73 @@
-//extern crate crossbeam_channel;
+//!
+//! test rust lock-free mpmc performance
+//! usage:
+//!   lfmpmc [sender_num [receiver_num [crossbeam|lfmpmc|lfmpmc-busy|lfmpmc-adaptive|unbounded]]]
+//! e.g.
+//!   lfmpmc 1 1 lfmpmc
+//!
 
-use crossbeam_channel::bounded;
+use queue::{SenderI, ReceiverI, SendError, RecvError, TrySendError, TryRecvError,
+            SendTimeoutError, RecvTimeoutError, WaitType};
+use queue::lfmpmc::new_lfmpmc;
+use queue::unbounded::new_unbounded;
 use std::{time, thread};
+use std::time::Instant;
 use std::str::FromStr;
 
+/// crossbeam channel adapted to SenderI/ReceiverI
+#[derive(Clone)]
+struct CbSender<T>(crossbeam_channel::Sender<T>);
+#[derive(Clone)]
+struct CbReceiver<T>(crossbeam_channel::Receiver<T>);
+
+impl<T> SenderI<T> for CbSender<T> {
+    fn send(&self, e: T) -> Result<(), SendError<T>> {
+        self.0.send(e).map_err(|e| SendError::Disconnected(e.0))
+    }
+    fn try_send(&self, e: T) -> Result<(), TrySendError<T>> {
+        self.0.try_send(e).map_err(|e| match e {
+            crossbeam_channel::TrySendError::Full(e) => TrySendError::Full(e),
+            crossbeam_channel::TrySendError::Disconnected(e) => TrySendError::Disconnected(e),
+        })
+    }
+    fn send_deadline(&self, e: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        self.0.send_timeout(e, timeout).map_err(|e| match e {
+            crossbeam_channel::SendTimeoutError::Timeout(e) => SendTimeoutError::Timeout(e),
+            crossbeam_channel::SendTimeoutError::Disconnected(e) => SendTimeoutError::Disconnected(e),
+        })
+    }
+}
+impl<T> ReceiverI<T> for CbReceiver<T> {
+    fn recv(&self) -> Result<T, RecvError> {
+        self.0.recv().map_err(|_| RecvError::Disconnected)
+    }
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.0.try_recv().map_err(|e| match e {
+            crossbeam_channel::TryRecvError::Empty => TryRecvError::Empty,
+            crossbeam_channel::TryRecvError::Disconnected => TryRecvError::Disconnected,
+        })
+    }
+    fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        self.0.recv_timeout(timeout).map_err(|e| match e {
+            crossbeam_channel::RecvTimeoutError::Timeout => RecvTimeoutError::Timeout,
+            crossbeam_channel::RecvTimeoutError::Disconnected => RecvTimeoutError::Disconnected,
+        })
+    }
+}
+
 struct PadI64 {
-    pub Val : i64,
+    pub val : i64,
     _pad : [i64;15],
 }
 impl PadI64 {
     pub fn new(val : i64) -> PadI64 {
-        PadI64{Val: val, _pad: [0;15]}
+        PadI64{val, _pad: [0;15]}
     }
     pub fn new_array(len: usize) -> Vec<PadI64> {
         let mut v = Vec::<PadI64>::with_capacity(len);
-        for i in 0..len {
+        for _ in 0..len {
             v.push(PadI64::new(0));
         }
         v
@@ -22,112 +75,136 @@ impl PadI64 {
 }
 
 fn print_result(rs_send : &[PadI64], rs_recv : &[PadI64], interval_s : i32) {
-    let mut sumTotalSend = 0i64;
-    let mut sumTotalRecv = 0i64;
-    let mut sumLastSend = 0i64;
-    let mut sumLastRecv = 0i64;
+    let mut sum_total_send = 0i64;
+    let mut sum_total_recv = 0i64;
     for v in rs_send {
-        sumTotalSend += v.Val;
+        sum_total_send += v.val;
     }
     for v in rs_recv {
-        sumTotalRecv += v.Val;
+        sum_total_recv += v.val;
     }
 
-    let begTotal = time::Instant::now();
-    let mut begLast = begTotal;
+    let beg_total = time::Instant::now();
     loop {
-        begLast = time::Instant::now();
-        sumLastRecv = sumTotalRecv;
-        sumLastSend = sumTotalSend;
+        let beg_last = time::Instant::now();
+        let sum_last_recv = sum_total_recv;
+        let sum_last_send = sum_total_send;
         thread::sleep(time::Duration::from_secs(interval_s as u64));
-        sumTotalSend = 0;
-        sumTotalRecv = 0;
+        sum_total_send = 0;
+        sum_total_recv = 0;
         for v in rs_send {
-            sumTotalSend += v.Val;
+            sum_total_send += v.val;
         }
         for v in rs_recv {
-            sumTotalRecv += v.Val;
+            sum_total_recv += v.val;
         }
-        let deltaSend = sumTotalSend - sumLastSend;
-        let deltaRecv = sumTotalRecv - sumLastRecv;
-        let elapseTotal = begTotal.elapsed();
-        let elapseLast = begLast.elapsed();
+        let delta_send = sum_total_send - sum_last_send;
+        let delta_recv = sum_total_recv - sum_last_recv;
+        let elapse_total = beg_total.elapsed();
+        let elapse_last = beg_last.elapsed();
 
-        print!("send: total: {:.0} send/ms, {:.0} ns/send. delta: {:.0} send/ms, {:.0} ns/send\n",
-               sumTotalSend/(elapseTotal.as_millis() as i64),
-               elapseTotal.as_nanos() as i64/sumTotalSend,
-               deltaSend/(elapseLast.as_millis() as i64),
-               elapseLast.as_nanos() as i64/deltaSend);
-        print!("recv: total: {:.0} recv/ms, {:.0} ns/recv. delta: {:.0} recv/ms, {:.0} ns/recv\n",
-               sumTotalRecv/(elapseTotal.as_millis() as i64),
-               elapseTotal.as_nanos() as i64/sumTotalRecv,
-               deltaRecv/(elapseLast.as_millis() as i64),
-               elapseLast.as_nanos() as i64/deltaRecv);
+        println!("send: total: {:.0} send/ms, {:.0} ns/send. delta: {:.0} send/ms, {:.0} ns/send",
+                 sum_total_send/(elapse_total.as_millis() as i64),
+                 elapse_total.as_nanos() as i64/sum_total_send,
+                 delta_send/(elapse_last.as_millis() as i64),
+                 elapse_last.as_nanos() as i64/delta_send);
+        println!("recv: total: {:.0} recv/ms, {:.0} ns/recv. delta: {:.0} recv/ms, {:.0} ns/recv",
+                 sum_total_recv/(elapse_total.as_millis() as i64),
+                 elapse_total.as_nanos() as i64/sum_total_recv,
+                 delta_recv/(elapse_last.as_millis() as i64),
+                 elapse_last.as_nanos() as i64/delta_recv);
     }
 }
 
-fn sendQ(q : &crossbeam_channel::Sender<i64>, rs : &mut i64) {
+fn send_q(q : &dyn SenderI<i64>, rs : &mut i64) {
     loop {
         q.send(1).unwrap();
         *rs += 1;
     }
 }
 
-fn recvQ(q : &crossbeam_channel::Receiver<i64>, rs : &mut i64) {
+fn recv_q(q : &dyn ReceiverI<i64>, rs : &mut i64) {
     loop {
-        let _ = q.recv().unwrap();
+        let _ = q.recv();
         *rs += 1;
     }
 }
 
-//#[test]
+fn run<S, R>(sdr: S, rvr: R, rs_send: &[PadI64], rs_recv: &[PadI64])
+    where S: SenderI<i64> + Clone + Send + 'static,
+          R: ReceiverI<i64> + Clone + Send + 'static {
+    for rs in rs_recv {
+        let sq = rvr.clone();
+        let rs = &rs.val as *const i64 as usize;
+        thread::spawn(move ||{
+            let rs = unsafe{(rs as *const i64 as *mut i64).as_mut().unwrap()};
+            recv_q(&sq, rs);
+        });
+    }
+
+    for rs in rs_send {
+        let sq = sdr.clone();
+        let rs = &rs.val as *const i64 as usize;
+        thread::spawn(move ||{
+            let rs = unsafe{(rs as *const i64 as *mut i64).as_mut().unwrap()};
+            send_q(&sq, rs);
+        });
+    }
+
+    print_result(rs_send, rs_recv, 10);
+}
+
 fn main() {
-    let mut nSend = 1;
-    let mut nRecv = 1;
+    let mut n_send = 1;
+    let mut n_recv = 1;
+    let mut queue = "crossbeam".to_string();
     let args : Vec<String> = std::env::args().collect();
-    // println!("{:?}", args);
     if args.len() > 1 {
         if let Ok(x) = i32::from_str(&args[1]) {
-            nSend = x;
+            n_send = x;
         } else {
             println!("invalid args: {}", &args[1]);
             return;
         }
         if args.len() > 2 {
             if let Ok(x) = i32::from_str(&args[2]) {
-                nRecv = x;
+                n_recv = x;
             } else {
                 println!("invalid args: {}", &args[2]);
                 return;
             }
         }
+        if args.len() > 3 {
+            queue = args[3].clone();
+        }
     }
-    let capacity = (2 << 16);
-    let rsSend = PadI64::new_array(nSend as usize);
-    let rsRecv = PadI64::new_array(nRecv as usize);
-    print!("======test rust mpmc({}): {} sender, {} receiver======\n",
-           capacity, nSend, nRecv);
-
-    let (sdr, rvr) = crossbeam_channel::bounded::<i64>(capacity);
-
-    for i in 0..nRecv {
-        let sq = rvr.clone();
-        let rs = &rsRecv[0].Val as *const i64 as usize;
-        thread::spawn(move ||{
-            let rs = unsafe{(rs as *const i64 as *mut i64).as_mut().unwrap()};
-            recvQ(&sq, rs);
-        });
-    }
+    let capacity = 2 << 16;
+    let rs_send = PadI64::new_array(n_send as usize);
+    let rs_recv = PadI64::new_array(n_recv as usize);
+    println!("======test rust {}({}): {} sender, {} receiver======",
+             queue, capacity, n_send, n_recv);
 
-    for i in 0..nSend {
-        let sq = sdr.clone();
-        let rs = &rsSend[0].Val as *const i64 as usize;
-        thread::spawn(move ||{
-            let rs = unsafe{(rs as *const i64 as *mut i64).as_mut().unwrap()};
-            sendQ(&sq, rs);
-        });
+    match queue.as_str() {
+        "crossbeam" => {
+            let (sdr, rvr) = crossbeam_channel::bounded::<i64>(capacity);
+            run(CbSender(sdr), CbReceiver(rvr), &rs_send, &rs_recv);
+        }
+        "lfmpmc" => {
+            let (sdr, rvr) = new_lfmpmc::<i64>(capacity, WaitType::SleepWait);
+            run(sdr, rvr, &rs_send, &rs_recv);
+        }
+        "lfmpmc-busy" => {
+            let (sdr, rvr) = new_lfmpmc::<i64>(capacity, WaitType::BusyWait);
+            run(sdr, rvr, &rs_send, &rs_recv);
+        }
+        "lfmpmc-adaptive" => {
+            let (sdr, rvr) = new_lfmpmc::<i64>(capacity, WaitType::adaptive());
+            run(sdr, rvr, &rs_send, &rs_recv);
+        }
+        "unbounded" => {
+            let (sdr, rvr) = new_unbounded::<i64>();
+            run(sdr, rvr, &rs_send, &rs_recv);
+        }
+        _ => println!("invalid args: {}", queue),
     }
-
-    print_result(&rsSend[..], &rsRecv[..], 10);
 }
-
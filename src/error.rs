@@ -0,0 +1,104 @@
+//!
+//! errors returned by the queues
+//!
+
+use std::fmt;
+
+/// All receivers are gone, the element is given back.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum SendError<T> {
+    Disconnected(T),
+}
+/// All senders are gone and the queue is drained.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum RecvError {
+    Disconnected,
+}
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "sending on a disconnected queue".fmt(f)
+    }
+}
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "receiving on an empty and disconnected queue".fmt(f)
+    }
+}
+impl<T: fmt::Debug> std::error::Error for SendError<T> {}
+impl std::error::Error for RecvError {}
+
+/// `try_send` failed, the element is given back.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TrySendError<T> {
+    Full(T),
+    Disconnected(T),
+}
+/// `try_recv` failed.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => "sending on a full queue".fmt(f),
+            TrySendError::Disconnected(_) => "sending on a disconnected queue".fmt(f),
+        }
+    }
+}
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => "receiving on an empty queue".fmt(f),
+            TryRecvError::Disconnected => "receiving on an empty and disconnected queue".fmt(f),
+        }
+    }
+}
+impl<T: fmt::Debug> std::error::Error for TrySendError<T> {}
+impl std::error::Error for TryRecvError {}
+
+/// `send_timeout`/`send_deadline` failed, the element is given back.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum SendTimeoutError<T> {
+    Timeout(T),
+    Disconnected(T),
+}
+/// `recv_timeout`/`recv_deadline` failed.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum RecvTimeoutError {
+    Timeout,
+    Disconnected,
+}
+impl<T> fmt::Display for SendTimeoutError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendTimeoutError::Timeout(_) => "timed out waiting on send operation".fmt(f),
+            SendTimeoutError::Disconnected(_) => "sending on a disconnected queue".fmt(f),
+        }
+    }
+}
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvTimeoutError::Timeout => "timed out waiting on receive operation".fmt(f),
+            RecvTimeoutError::Disconnected => "receiving on an empty and disconnected queue".fmt(f),
+        }
+    }
+}
+impl<T: fmt::Debug> std::error::Error for SendTimeoutError<T> {}
+impl std::error::Error for RecvTimeoutError {}
+impl<T> From<SendError<T>> for SendTimeoutError<T> {
+    fn from(err: SendError<T>) -> Self {
+        match err {
+            SendError::Disconnected(e) => SendTimeoutError::Disconnected(e),
+        }
+    }
+}
+impl From<RecvError> for RecvTimeoutError {
+    fn from(err: RecvError) -> Self {
+        match err {
+            RecvError::Disconnected => RecvTimeoutError::Disconnected,
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//!
+//! linux futex wait/wake, used by `WaitType::Futex`
+//!
+
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Instant;
+
+/// A 32-bit sequence word to sleep on, with the number of waiters telling
+/// the other side whether it has to make the wake syscall.
+#[repr(C)]
+pub(crate) struct FutexSem {
+    seq: AtomicU32,
+    waiters: AtomicU32,
+    /// FUTEX_PRIVATE_FLAG, or 0 if it is in memory shared between processes
+    flags: i32,
+}
+
+impl FutexSem {
+    pub(crate) fn new() -> FutexSem {
+        FutexSem { seq: AtomicU32::new(0), waiters: AtomicU32::new(0), flags: libc::FUTEX_PRIVATE_FLAG }
+    }
+    /// For a word in shared memory, waited on from other processes.
+    pub(crate) fn new_shared() -> FutexSem {
+        FutexSem { seq: AtomicU32::new(0), waiters: AtomicU32::new(0), flags: 0 }
+    }
+
+    /// Sleep while `blocked()` is true, until woken up or `deadline`.
+    /// May return early, callers check their condition again.
+    pub(crate) fn wait(&self, blocked: impl Fn() -> bool, deadline: Option<Instant>) {
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        // the seq is loaded before `blocked` is checked, so a wake after the
+        // check changes it and the syscall returns at once
+        let seq = self.seq.load(Ordering::SeqCst);
+        if blocked() {
+            let ts = deadline.map(|d| {
+                let t = d.saturating_duration_since(Instant::now());
+                libc::timespec {
+                    tv_sec: t.as_secs() as libc::time_t,
+                    tv_nsec: t.subsec_nanos() as libc::c_long,
+                }
+            });
+            unsafe {
+                libc::syscall(libc::SYS_futex, self.seq.as_ptr(),
+                              libc::FUTEX_WAIT | self.flags, seq,
+                              ts.as_ref().map_or(ptr::null(), |t| t as *const libc::timespec));
+            }
+        }
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Wake up the waiters, if there are any.
+    #[inline]
+    pub(crate) fn wake(&self) {
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            self.wake_all();
+        }
+    }
+
+    pub(crate) fn wake_all(&self) {
+        self.seq.fetch_add(1, Ordering::SeqCst);
+        unsafe {
+            libc::syscall(libc::SYS_futex, self.seq.as_ptr(),
+                          libc::FUTEX_WAKE | self.flags, i32::MAX);
+        }
+    }
+}
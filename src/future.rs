@@ -0,0 +1,101 @@
+//!
+//! futures of `send_async`/`recv_async`, with the `async` feature
+//!
+//! A future tries to send or receive. If it has to wait, it registers the
+//! task's `Waker` in the queue's `WakerSet` and tries once more, so a wake up
+//! between the two tries is not lost. The queue wakes all registered tasks
+//! when it leaves the full or the empty state, and when it is disconnected.
+//! Nothing depends on a particular executor.
+//!
+
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use crate::{SenderI, ReceiverI, SendError, RecvError, TrySendError, TryRecvError};
+use crate::waker::WakerSet;
+
+/// Future of `send_async`, resolves once the element is in the queue.
+pub struct SendFuture<'a, T, S: ?Sized> {
+    sender: &'a S,
+    wakers: &'a WakerSet,
+    elem: Option<T>,
+}
+impl<'a, T, S: SenderI<T> + ?Sized> SendFuture<'a, T, S> {
+    pub(crate) fn new(sender: &'a S, wakers: &'a WakerSet, e: T) -> Self {
+        SendFuture { sender, wakers, elem: Some(e) }
+    }
+}
+// the element is never pinned
+impl<T, S: ?Sized> Unpin for SendFuture<'_, T, S> {}
+
+impl<T, S: SenderI<T> + ?Sized> Future for SendFuture<'_, T, S> {
+    type Output = Result<(), SendError<T>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut e = this.elem.take().expect("SendFuture polled after completion");
+        let mut registered = false;
+        loop {
+            match this.sender.try_send(e) {
+                Ok(()) => return Poll::Ready(Ok(())),
+                Err(TrySendError::Disconnected(e)) => return Poll::Ready(Err(SendError::Disconnected(e))),
+                Err(TrySendError::Full(full)) if registered => {
+                    this.elem = Some(full);
+                    return Poll::Pending;
+                }
+                Err(TrySendError::Full(full)) => {
+                    this.wakers.register(cx.waker());
+                    registered = true;
+                    e = full;
+                }
+            }
+        }
+    }
+}
+
+/// Future of `recv_async`, resolves with an element or once the queue is
+/// disconnected and drained.
+pub struct RecvFuture<'a, T, R: ?Sized> {
+    receiver: &'a R,
+    wakers: &'a WakerSet,
+    _elem: PhantomData<fn() -> T>,
+}
+impl<'a, T, R: ReceiverI<T> + ?Sized> RecvFuture<'a, T, R> {
+    pub(crate) fn new(receiver: &'a R, wakers: &'a WakerSet) -> Self {
+        RecvFuture { receiver, wakers, _elem: PhantomData }
+    }
+}
+
+impl<T, R: ReceiverI<T> + ?Sized> Future for RecvFuture<'_, T, R> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut registered = false;
+        loop {
+            match self.receiver.try_recv() {
+                Ok(e) => return Poll::Ready(Ok(e)),
+                Err(TryRecvError::Disconnected) => return Poll::Ready(Err(RecvError::Disconnected)),
+                Err(TryRecvError::Empty) if registered => return Poll::Pending,
+                Err(TryRecvError::Empty) => {
+                    self.wakers.register(cx.waker());
+                    registered = true;
+                }
+            }
+        }
+    }
+}
+
+/// Run a future on the current thread, parking it while the future is pending.
+#[cfg(test)]
+pub(crate) fn block_on<F: Future>(f: F) -> F::Output {
+    let waker = crate::waker::thread_waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut f = Box::pin(f);
+    loop {
+        match f.as_mut().poll(&mut cx) {
+            Poll::Ready(r) => return r,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//!
+//! iterators over the elements of a Receiver
+//!
+//! Every Receiver has `iter`, `try_iter` and `drain`, and `IntoIterator` for
+//! itself and for a reference to it:
+//!
+//! * `iter`/`into_iter`: waits for each element, ends once the queue is
+//!   disconnected and drained
+//! * `try_iter`: the elements available without waiting, ends at the first
+//!   empty queue
+//! * `drain`: like `try_iter`, but at most the number of elements in the queue
+//!   when it was called, so it ends even if senders keep sending
+//!
+
+use std::marker::PhantomData;
+use crate::ReceiverI;
+
+/// Blocking iterator, see `Receiver::iter`.
+pub struct Iter<'a, T, R: ?Sized> {
+    receiver: &'a R,
+    _elem: PhantomData<fn() -> T>,
+}
+impl<'a, T, R: ReceiverI<T> + ?Sized> Iter<'a, T, R> {
+    pub(crate) fn new(receiver: &'a R) -> Self {
+        Iter { receiver, _elem: PhantomData }
+    }
+}
+impl<T, R: ReceiverI<T> + ?Sized> Iterator for Iter<'_, T, R> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.receiver.recv().ok()
+    }
+}
+
+/// Non-blocking iterator, see `Receiver::try_iter`.
+pub struct TryIter<'a, T, R: ?Sized> {
+    receiver: &'a R,
+    _elem: PhantomData<fn() -> T>,
+}
+impl<'a, T, R: ReceiverI<T> + ?Sized> TryIter<'a, T, R> {
+    pub(crate) fn new(receiver: &'a R) -> Self {
+        TryIter { receiver, _elem: PhantomData }
+    }
+}
+impl<T, R: ReceiverI<T> + ?Sized> Iterator for TryIter<'_, T, R> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// Non-blocking iterator over the elements queued at its creation, see
+/// `Receiver::drain`.
+pub struct Drain<'a, T, R: ?Sized> {
+    receiver: &'a R,
+    left: usize,
+    _elem: PhantomData<fn() -> T>,
+}
+impl<'a, T, R: ReceiverI<T> + ?Sized> Drain<'a, T, R> {
+    pub(crate) fn new(receiver: &'a R, len: usize) -> Self {
+        Drain { receiver, left: len, _elem: PhantomData }
+    }
+}
+impl<T, R: ReceiverI<T> + ?Sized> Iterator for Drain<'_, T, R> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+        let e = self.receiver.try_recv().ok();
+        if e.is_none() {
+            // other receivers were faster
+            self.left = 0;
+        }
+        e
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.left))
+    }
+}
+
+/// Owning blocking iterator, see `IntoIterator` for the Receivers.
+pub struct IntoIter<T, R> {
+    receiver: R,
+    _elem: PhantomData<fn() -> T>,
+}
+impl<T, R: ReceiverI<T>> IntoIter<T, R> {
+    pub(crate) fn new(receiver: R) -> Self {
+        IntoIter { receiver, _elem: PhantomData }
+    }
+}
+impl<T, R: ReceiverI<T>> Iterator for IntoIter<T, R> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.receiver.recv().ok()
+    }
+}
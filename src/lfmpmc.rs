@@ -0,0 +1,826 @@
+//!
+//! rust lock-free mpmc queue, port of lfmpmc/go
+//!
+//! Every slot carries a stamp. A slot is free for the lap `tail` when
+//! `stamp == tail`, and holds an element for the lap `head` when
+//! `stamp == head + 1`. Producers/consumers reserve a slot by CAS on
+//! `i_idx`/`o_idx`, then publish the slot by storing the new stamp.
+//!
+//! There is no in-place `reserve` as in `spsc`: the CAS hands the slot to
+//! the consumer of that lap, so an uncommitted reservation could not be given
+//! back without every consumer handling skipped slots.
+//!
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::ptr;
+use std::time::Instant;
+#[cfg(target_os = "linux")]
+use crate::futex::FutexSem;
+use crate::{SenderI, ReceiverI, WaitType, SendError, RecvError,
+            TrySendError, TryRecvError, SendTimeoutError, RecvTimeoutError};
+use crate::iter::{Iter, TryIter, Drain, IntoIter};
+
+pub struct Sender<T> {
+    inner : Arc<LfMpmcQueue<T>>,
+}
+impl<T> SenderI<T> for Sender<T> {
+    fn send(&self, e: T) -> Result<(), SendError<T>> {
+        self.inner.push(e)
+    }
+    fn try_send(&self, e: T) -> Result<(), TrySendError<T>> {
+        self.inner.try_push(e)
+    }
+    fn send_deadline(&self, e: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        self.inner.push_until(e, Some(deadline))
+    }
+}
+impl<T> Sender<T> {
+    /// See `LfMpmcQueue::len`.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+    /// See `LfMpmcQueue::is_empty`.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+    /// See `LfMpmcQueue::is_full`.
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+    /// See `LfMpmcQueue::capacity`.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See `LfMpmcQueue::is_disconnected`.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.is_disconnected()
+    }
+    /// Close the queue, see `LfMpmcQueue::close`.
+    pub fn close(&self) -> bool {
+        self.inner.close()
+    }
+}
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.inner.senders.fetch_add(1, Ordering::SeqCst);
+        Sender { inner: self.inner.clone() }
+    }
+}
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.inner.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.close();
+        }
+    }
+}
+pub struct Receiver<T> {
+    inner: Arc<LfMpmcQueue<T>>,
+}
+impl<T> ReceiverI<T> for Receiver<T> {
+    fn recv(&self) -> Result<T, RecvError> {
+        self.inner.pop()
+    }
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.inner.try_pop()
+    }
+    fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.inner.pop_until(Some(deadline))
+    }
+}
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.inner.receivers.fetch_add(1, Ordering::SeqCst);
+        Receiver { inner: self.inner.clone() }
+    }
+}
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if self.inner.receivers.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.close();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// See `LfMpmcQueue::len`.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+    /// See `LfMpmcQueue::is_empty`.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+    /// See `LfMpmcQueue::is_full`.
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+    /// See `LfMpmcQueue::capacity`.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See `LfMpmcQueue::is_disconnected`.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.is_disconnected()
+    }
+    /// Iterate over the elements, waiting for each one, until the queue is
+    /// disconnected and drained.
+    pub fn iter(&self) -> Iter<'_, T, Self> {
+        Iter::new(self)
+    }
+    /// Iterate over the elements available without waiting.
+    pub fn try_iter(&self) -> TryIter<'_, T, Self> {
+        TryIter::new(self)
+    }
+    /// Iterate over at most the elements in the queue now.
+    pub fn drain(&self) -> Drain<'_, T, Self> {
+        Drain::new(self, self.inner.len())
+    }
+}
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T, Self>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::new(self)
+    }
+}
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T, Receiver<T>>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub fn new_lfmpmc<T>(cap : usize, wait_mode : WaitType) -> (Sender<T>, Receiver<T>) {
+    let qs = Arc::new(LfMpmcQueue::<T>::new(cap, wait_mode));
+    qs.senders.store(1, Ordering::SeqCst);
+    qs.receivers.store(1, Ordering::SeqCst);
+    let qr = qs.clone();
+    (Sender { inner: qs }, Receiver { inner: qr })
+}
+
+struct Slot<T> {
+    stamp: AtomicUsize,
+    msg: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// A reserved slot. `slot` is null if the queue is closed.
+struct Token<T> {
+    new_stamp: usize,
+    slot: *const Slot<T>,
+}
+
+pub struct LfMpmcQueue<T> {
+    i_idx: AtomicUsize, // queue tail
+    _pad2: [i64; 7],
+    o_idx: AtomicUsize, // queue head
+    _pad3: [i64; 7],
+    capacity: usize,
+    mark_bit: usize,
+    one_lap: usize,
+    wait_mode: WaitType,
+    buf: Box<[Slot<T>]>,
+    n_wait_room: AtomicUsize,
+    n_wait_elem: AtomicUsize,
+    sem_room: (Mutex<()>, Condvar),
+    sem_elem: (Mutex<()>, Condvar),
+    /// the senders sleep on it in Futex mode
+    #[cfg(target_os = "linux")]
+    futex_room: FutexSem,
+    /// the receivers sleep on it in Futex mode
+    #[cfg(target_os = "linux")]
+    futex_elem: FutexSem,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+}
+
+impl<T> LfMpmcQueue<T> {
+    pub fn new(cap: usize, wait_mode: WaitType) -> LfMpmcQueue<T> {
+        assert!(cap >= 1, "capacity too small");
+
+        let mark_bit = (cap + 1).next_power_of_two();
+        let buf = (0..cap).map(|i| Slot {
+            stamp: AtomicUsize::new(i),
+            msg: UnsafeCell::new(MaybeUninit::uninit()),
+        }).collect::<Vec<_>>().into_boxed_slice();
+
+        LfMpmcQueue {
+            i_idx: AtomicUsize::new(0),
+            _pad2: [0; 7],
+            o_idx: AtomicUsize::new(0),
+            _pad3: [0; 7],
+            capacity: cap,
+            mark_bit,
+            one_lap: mark_bit << 1,
+            wait_mode,
+            buf,
+            n_wait_room: AtomicUsize::new(0),
+            n_wait_elem: AtomicUsize::new(0),
+            sem_room: (Mutex::new(()), Default::default()),
+            sem_elem: (Mutex::new(()), Default::default()),
+            #[cfg(target_os = "linux")]
+            futex_room: FutexSem::new(),
+            #[cfg(target_os = "linux")]
+            futex_elem: FutexSem::new(),
+            senders: AtomicUsize::new(0),
+            receivers: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        let head = self.o_idx.load(Ordering::SeqCst);
+        let tail = self.i_idx.load(Ordering::SeqCst);
+        head.wrapping_add(self.one_lap) == tail & !self.mark_bit
+    }
+    pub fn is_empty(&self) -> bool {
+        let head = self.o_idx.load(Ordering::SeqCst);
+        let tail = self.i_idx.load(Ordering::SeqCst);
+        head == tail & !self.mark_bit
+    }
+    /// Number of elements in the queue, see the crate docs on how current
+    /// it is. Read again until `i_idx` did not move while reading `o_idx`.
+    pub fn len(&self) -> usize {
+        loop {
+            let tail = self.i_idx.load(Ordering::SeqCst);
+            let head = self.o_idx.load(Ordering::SeqCst);
+            if self.i_idx.load(Ordering::SeqCst) != tail {
+                continue;
+            }
+            let hix = head & (self.mark_bit - 1);
+            let tix = tail & (self.mark_bit - 1);
+            return if hix < tix {
+                tix - hix
+            } else if hix > tix {
+                self.capacity - hix + tix
+            } else if tail & !self.mark_bit == head {
+                0
+            } else {
+                self.capacity
+            };
+        }
+    }
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    pub fn is_closed(&self) -> bool {
+        self.i_idx.load(Ordering::SeqCst) & self.mark_bit != 0
+    }
+    /// Same as `is_closed`: all Senders or all Receivers are gone, or `close`
+    /// was called. Stays true once true, elements sent before can still be received.
+    #[inline]
+    pub fn is_disconnected(&self) -> bool {
+        self.is_closed()
+    }
+
+    /// Returns None if the queue is full.
+    fn reserve_room(&self) -> Option<Token<T>> {
+        let mut snoozer = self.wait_mode.snoozer();
+        let mut tail = self.i_idx.load(Ordering::SeqCst);
+        loop {
+            if tail & self.mark_bit != 0 {
+                // queue is closed
+                return Some(Token { new_stamp: 0, slot: ptr::null() });
+            }
+
+            let index = tail & (self.mark_bit - 1);
+            let lap = tail & !(self.one_lap - 1);
+            let slot = &self.buf[index];
+            let stamp = slot.stamp.load(Ordering::SeqCst);
+
+            if tail == stamp {
+                // valid room
+                let next_tail = if index + 1 < self.capacity {
+                    tail + 1
+                } else {
+                    lap.wrapping_add(self.one_lap)
+                };
+
+                match self.i_idx.compare_exchange_weak(tail, next_tail,
+                                                       Ordering::SeqCst, Ordering::SeqCst) {
+                    // ok, I reserved room
+                    Ok(_) => return Some(Token { new_stamp: tail + 1, slot }),
+                    // No, the room has been reserved by others, try again.
+                    Err(t) => {
+                        snoozer.spin();
+                        tail = t;
+                    }
+                }
+            } else if stamp.wrapping_add(self.one_lap) == tail + 1 {
+                // maybe full
+                let head = self.o_idx.load(Ordering::SeqCst);
+                if head.wrapping_add(self.one_lap) == tail {
+                    // queue is full
+                    return None;
+                }
+                snoozer.spin();
+                tail = self.i_idx.load(Ordering::SeqCst);
+            } else {
+                // tail may be got by other producers, try again
+                snoozer.snooze();
+                tail = self.i_idx.load(Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Returns None if the queue is empty.
+    fn reserve_elem(&self) -> Option<Token<T>> {
+        let mut snoozer = self.wait_mode.snoozer();
+        let mut head = self.o_idx.load(Ordering::SeqCst);
+        loop {
+            let index = head & (self.mark_bit - 1);
+            let lap = head & !(self.one_lap - 1);
+            let slot = &self.buf[index];
+            let stamp = slot.stamp.load(Ordering::SeqCst);
+
+            if head + 1 == stamp {
+                // good product
+                let next_head = if index + 1 < self.capacity {
+                    head + 1
+                } else {
+                    lap.wrapping_add(self.one_lap)
+                };
+
+                match self.o_idx.compare_exchange_weak(head, next_head,
+                                                       Ordering::SeqCst, Ordering::SeqCst) {
+                    Ok(_) => return Some(Token { new_stamp: head.wrapping_add(self.one_lap), slot }),
+                    Err(h) => {
+                        snoozer.spin();
+                        head = h;
+                    }
+                }
+            } else if head == stamp {
+                // maybe empty
+                let tail = self.i_idx.load(Ordering::SeqCst);
+                if tail & !self.mark_bit == head {
+                    if tail & self.mark_bit != 0 {
+                        // queue closed
+                        return Some(Token { new_stamp: 0, slot: ptr::null() });
+                    }
+                    // empty
+                    return None;
+                }
+                snoozer.spin();
+                head = self.o_idx.load(Ordering::SeqCst);
+            } else {
+                snoozer.snooze();
+                head = self.o_idx.load(Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Write an element into a reserved room and publish it.
+    fn write(&self, token: Token<T>, e: T) {
+        unsafe {
+            let slot = &*token.slot;
+            slot.msg.get().write(MaybeUninit::new(e));
+            slot.stamp.store(token.new_stamp, Ordering::SeqCst);
+        }
+
+        // notify all consumers
+        #[cfg(target_os = "linux")]
+        if let WaitType::Futex = self.wait_mode {
+            self.futex_elem.wake();
+            return;
+        }
+        if self.n_wait_elem.load(Ordering::SeqCst) > 0 {
+            let _g = self.sem_elem.0.lock().unwrap();
+            self.sem_elem.1.notify_all();
+        }
+    }
+
+    /// Read an element out of a reserved slot and release the slot.
+    fn read(&self, token: Token<T>) -> T {
+        let e = unsafe {
+            let slot = &*token.slot;
+            let e = slot.msg.get().read().assume_init();
+            slot.stamp.store(token.new_stamp, Ordering::SeqCst);
+            e
+        };
+
+        // notify all producers
+        #[cfg(target_os = "linux")]
+        if let WaitType::Futex = self.wait_mode {
+            self.futex_room.wake();
+            return e;
+        }
+        if self.n_wait_room.load(Ordering::SeqCst) > 0 {
+            let _g = self.sem_room.0.lock().unwrap();
+            self.sem_room.1.notify_all();
+        }
+        e
+    }
+
+    /// Push an element, waiting for room according to `wait_mode`.
+    /// Gives the element back if the queue is closed.
+    pub fn push(&self, e: T) -> Result<(), SendError<T>> {
+        self.push_until(e, None).map_err(|err| match err {
+            SendTimeoutError::Disconnected(e) => SendError::Disconnected(e),
+            SendTimeoutError::Timeout(_) => unreachable!(),
+        })
+    }
+
+    /// Push, giving up at `deadline` if there is one.
+    fn push_until(&self, e: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        loop {
+            // try push several times
+            let mut snoozer = self.wait_mode.snoozer();
+            loop {
+                if let Some(token) = self.reserve_room() {
+                    if token.slot.is_null() {
+                        return Err(SendTimeoutError::Disconnected(e));
+                    }
+                    self.write(token, e);
+                    return Ok(());
+                }
+
+                // reserve room fail
+                if snoozer.completed() {
+                    break;
+                } else {
+                    snoozer.snooze();
+                }
+            }
+
+            if let Some(d) = deadline {
+                if Instant::now() >= d {
+                    return Err(SendTimeoutError::Timeout(e));
+                }
+            }
+
+            // wait room
+            #[cfg(target_os = "linux")]
+            if let WaitType::Futex = self.wait_mode {
+                self.futex_room.wait(|| self.is_full() && !self.is_closed(), deadline);
+                continue;
+            }
+            if self.wait_mode.parks() {
+                let g = self.sem_room.0.lock().unwrap();
+                self.n_wait_room.fetch_add(1, Ordering::SeqCst);
+                if self.is_full() && !self.is_closed() {
+                    match deadline {
+                        None => drop(self.sem_room.1.wait(g).unwrap()),
+                        Some(d) => {
+                            let timeout = d.saturating_duration_since(Instant::now());
+                            drop(self.sem_room.1.wait_timeout(g, timeout).unwrap())
+                        }
+                    }
+                } else {
+                    drop(g);
+                }
+                self.n_wait_room.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Pop an element, waiting for one according to `wait_mode`.
+    /// Fails once the queue is closed and drained.
+    pub fn pop(&self) -> Result<T, RecvError> {
+        self.pop_until(None).map_err(|_| RecvError::Disconnected)
+    }
+
+    /// Pop, giving up at `deadline` if there is one.
+    fn pop_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            // reserve elem several times
+            let mut snoozer = self.wait_mode.snoozer();
+            loop {
+                if let Some(token) = self.reserve_elem() {
+                    if token.slot.is_null() {
+                        return Err(RecvTimeoutError::Disconnected);
+                    }
+                    return Ok(self.read(token));
+                }
+
+                if snoozer.completed() {
+                    break;
+                } else {
+                    snoozer.snooze();
+                }
+            }
+
+            if let Some(d) = deadline {
+                if Instant::now() >= d {
+                    return Err(RecvTimeoutError::Timeout);
+                }
+            }
+
+            // wait elem
+            #[cfg(target_os = "linux")]
+            if let WaitType::Futex = self.wait_mode {
+                self.futex_elem.wait(|| self.is_empty() && !self.is_closed(), deadline);
+                continue;
+            }
+            if self.wait_mode.parks() {
+                let g = self.sem_elem.0.lock().unwrap();
+                self.n_wait_elem.fetch_add(1, Ordering::SeqCst);
+                if self.is_empty() && !self.is_closed() {
+                    match deadline {
+                        None => drop(self.sem_elem.1.wait(g).unwrap()),
+                        Some(d) => {
+                            let timeout = d.saturating_duration_since(Instant::now());
+                            drop(self.sem_elem.1.wait_timeout(g, timeout).unwrap())
+                        }
+                    }
+                } else {
+                    drop(g);
+                }
+                self.n_wait_elem.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    pub fn try_push(&self, e: T) -> Result<(), TrySendError<T>> {
+        match self.reserve_room() {
+            Some(token) if token.slot.is_null() => Err(TrySendError::Disconnected(e)),
+            Some(token) => {
+                self.write(token, e);
+                Ok(())
+            }
+            None => Err(TrySendError::Full(e)),
+        }
+    }
+
+    pub fn try_pop(&self) -> Result<T, TryRecvError> {
+        match self.reserve_elem() {
+            Some(token) if token.slot.is_null() => Err(TryRecvError::Disconnected),
+            Some(token) => Ok(self.read(token)),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Close the queue: pending elements can still be popped, further pushes fail.
+    /// Called when the last Sender or the last Receiver is dropped.
+    /// Returns false if the queue was already closed.
+    pub fn close(&self) -> bool {
+        let tail = self.i_idx.fetch_or(self.mark_bit, Ordering::SeqCst);
+        if tail & self.mark_bit != 0 {
+            return false;
+        }
+        #[cfg(target_os = "linux")]
+        {
+            self.futex_room.wake();
+            self.futex_elem.wake();
+        }
+        {
+            let _g = self.sem_room.0.lock().unwrap();
+            self.sem_room.1.notify_all();
+        }
+        {
+            let _g = self.sem_elem.0.lock().unwrap();
+            self.sem_elem.1.notify_all();
+        }
+        true
+    }
+}
+impl<T> Drop for LfMpmcQueue<T> {
+    fn drop(&mut self) {
+        // remove all elements
+        let head = *self.o_idx.get_mut();
+        let tail = *self.i_idx.get_mut() & !self.mark_bit;
+        let hix = head & (self.mark_bit - 1);
+        let tix = tail & (self.mark_bit - 1);
+        let len = if hix < tix {
+            tix - hix
+        } else if hix > tix {
+            self.capacity - hix + tix
+        } else if tail == head {
+            0
+        } else {
+            self.capacity
+        };
+
+        for i in 0..len {
+            let index = (hix + i) % self.capacity;
+            unsafe {
+                (*self.buf[index].msg.get()).assume_init_drop();
+            }
+        }
+    }
+}
+unsafe impl<T: Send> Send for LfMpmcQueue<T>{}
+unsafe impl<T: Send> Sync for LfMpmcQueue<T>{}
+
+impl<T> SenderI<T> for LfMpmcQueue<T> {
+    fn send(&self, e: T) -> Result<(), SendError<T>> {
+        self.push(e)
+    }
+    fn try_send(&self, e: T) -> Result<(), TrySendError<T>> {
+        self.try_push(e)
+    }
+    fn send_deadline(&self, e: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        self.push_until(e, Some(deadline))
+    }
+}
+impl<T> ReceiverI<T> for LfMpmcQueue<T> {
+    fn recv(&self) -> Result<T, RecvError> {
+        self.pop()
+    }
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.try_pop()
+    }
+    fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.pop_until(Some(deadline))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::lfmpmc::{LfMpmcQueue, new_lfmpmc};
+    use crate::{SenderI, ReceiverI, WaitType, SendError, RecvError,
+                TrySendError, TryRecvError, SendTimeoutError, RecvTimeoutError};
+    use std::time::{Duration, Instant};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn send(w : &dyn SenderI<i64>) {
+        for i in 0..10 {
+            w.send(i as i64).unwrap();
+        }
+    }
+    fn recv(r : &dyn ReceiverI<i64>) {
+        for i in 0..10 {
+            let e = r.recv().unwrap();
+            assert_eq!(e, i as i64);
+        }
+    }
+
+    #[test]
+    fn test1() {
+        let q = LfMpmcQueue::<i64>::new(2<<5, WaitType::SleepWait);
+        send(&q);
+        recv(&q);
+
+        let q = Arc::new(q);
+        let qc = q.clone();
+        let t = thread::spawn(move ||{ send(&*qc);});
+        recv(&*q);
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test2(){
+        for wait_mode in [WaitType::BusyWait, WaitType::SleepWait, WaitType::adaptive()] {
+            let (wr, rd) = new_lfmpmc::<i64>(3, wait_mode);
+            let mut ts = Vec::new();
+            for _ in 0..4 {
+                let wr = wr.clone();
+                ts.push(thread::spawn(move || {
+                    for i in 0..10000 {
+                        wr.send(i).unwrap();
+                    }
+                }));
+            }
+            let mut rs = Vec::new();
+            for _ in 0..4 {
+                let rd = rd.clone();
+                rs.push(thread::spawn(move || {
+                    (0..10000).map(|_| rd.recv().unwrap()).sum::<i64>()
+                }));
+            }
+            for t in ts {
+                t.join().unwrap();
+            }
+            let sum: i64 = rs.into_iter().map(|t| t.join().unwrap()).sum();
+            assert_eq!(sum, 4 * (0..10000).sum::<i64>());
+        }
+    }
+
+    #[test]
+    fn test_close() {
+        let q = LfMpmcQueue::<String>::new(4, WaitType::SleepWait);
+        q.push("a".to_string()).unwrap();
+        q.push("b".to_string()).unwrap();
+        assert!(q.close());
+        assert!(!q.close());
+        assert_eq!(q.push("c".to_string()), Err(SendError::Disconnected("c".to_string())));
+        assert_eq!(q.pop().as_deref(), Ok("a"));
+        // "b" is dropped with the queue
+    }
+
+    #[test]
+    fn test_close_wakes_receiver() {
+        let q = Arc::new(LfMpmcQueue::<i64>::new(1, WaitType::SleepWait));
+        let qc = q.clone();
+        let t = thread::spawn(move || qc.pop());
+        thread::sleep(std::time::Duration::from_millis(50));
+        q.close();
+        assert_eq!(t.join().unwrap(), Err(RecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (wr, rd) = new_lfmpmc::<i64>(2<<6, WaitType::SleepWait);
+        let wr2 = wr.clone();
+        send(&wr);
+        drop(wr);
+        wr2.send(10).unwrap();
+        drop(wr2);
+        recv(&rd);
+        assert_eq!(rd.recv(), Ok(10));
+        assert_eq!(rd.recv(), Err(RecvError::Disconnected));
+
+        let (wr, rd) = new_lfmpmc::<i64>(4, WaitType::SleepWait);
+        drop(rd);
+        assert_eq!(wr.send(1), Err(SendError::Disconnected(1)));
+    }
+
+    #[test]
+    fn test_try() {
+        let (wr, rd) = new_lfmpmc::<i64>(2, WaitType::SleepWait);
+        assert_eq!(rd.try_recv(), Err(TryRecvError::Empty));
+        wr.try_send(1).unwrap();
+        wr.try_send(2).unwrap();
+        assert_eq!(wr.try_send(3), Err(TrySendError::Full(3)));
+        assert_eq!(rd.try_recv(), Ok(1));
+        wr.try_send(3).unwrap();
+        drop(wr);
+        assert_eq!(rd.try_recv(), Ok(2));
+        assert_eq!(rd.try_recv(), Ok(3));
+        assert_eq!(rd.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_timeout() {
+        for wait_mode in [WaitType::BusyWait, WaitType::SleepWait, WaitType::adaptive()] {
+            let (wr, rd) = new_lfmpmc::<i64>(1, wait_mode);
+            let begin = Instant::now();
+            assert_eq!(rd.recv_timeout(Duration::from_millis(20)), Err(RecvTimeoutError::Timeout));
+            assert!(begin.elapsed() >= Duration::from_millis(20));
+
+            wr.send_timeout(1, Duration::from_millis(20)).unwrap();
+            let begin = Instant::now();
+            assert_eq!(wr.send_timeout(2, Duration::from_millis(20)), Err(SendTimeoutError::Timeout(2)));
+            assert!(begin.elapsed() >= Duration::from_millis(20));
+
+            assert_eq!(rd.recv_deadline(Instant::now() + Duration::from_millis(20)), Ok(1));
+            drop(wr);
+            assert_eq!(rd.recv_timeout(Duration::from_secs(10)), Err(RecvTimeoutError::Disconnected));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_futex() {
+        // several senders and receivers sleep on the same futex
+        let (wr, rd) = new_lfmpmc::<i64>(3, WaitType::Futex);
+        let ts: Vec<_> = (0..4).map(|_| {
+            let wr = wr.clone();
+            thread::spawn(move || {
+                for i in 0..10000 {
+                    wr.send(i).unwrap();
+                }
+            })
+        }).collect();
+        let rs: Vec<_> = (0..4).map(|_| {
+            let rd = rd.clone();
+            thread::spawn(move || (0..10000).map(|_| rd.recv().unwrap()).sum::<i64>())
+        }).collect();
+        for t in ts {
+            t.join().unwrap();
+        }
+        let sum: i64 = rs.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(sum, 4 * (0..10000).sum::<i64>());
+
+        let begin = Instant::now();
+        assert_eq!(rd.recv_timeout(Duration::from_millis(20)), Err(RecvTimeoutError::Timeout));
+        assert!(begin.elapsed() >= Duration::from_millis(20));
+        let rd2 = rd.clone();
+        let t = thread::spawn(move || rd2.recv());
+        thread::sleep(Duration::from_millis(50));
+        drop(wr);
+        assert_eq!(t.join().unwrap(), Err(RecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_iter() {
+        // drain counts the elements across the wrap around
+        let (wr, rd) = new_lfmpmc::<usize>(3, WaitType::SleepWait);
+        for round in 0..4 {
+            for i in 0..3 {
+                wr.send(round * 3 + i).unwrap();
+            }
+            assert_eq!(rd.drain().collect::<Vec<_>>(), [round * 3, round * 3 + 1, round * 3 + 2]);
+            assert_eq!(rd.drain().count(), 0);
+        }
+        wr.send(12).unwrap();
+        drop(wr);
+        assert_eq!(rd.iter().collect::<Vec<_>>(), [12]);
+    }
+
+    #[test]
+    fn test_len() {
+        let (wr, rd) = new_lfmpmc::<i64>(2, WaitType::SleepWait);
+        assert_eq!((wr.len(), wr.capacity()), (0, 2));
+        assert!(wr.is_empty());
+        wr.send(1).unwrap();
+        wr.send(2).unwrap();
+        assert!(rd.is_full());
+        assert_eq!(rd.recv(), Ok(1));
+        assert_eq!(rd.len(), 1);
+        drop(rd);
+        assert!(wr.is_disconnected());
+    }
+}
@@ -0,0 +1,123 @@
+//!
+//! queue: spsc, mpmc and lock-free mpmc queues.
+//!
+//! Every queue is created as a `(Sender, Receiver)` pair, e.g. `spsc::new_spsc`,
+//! `mpsc::new_mpsc`, `mpmc::new_mpmc`, `lfmpmc::new_lfmpmc`, the unbounded
+//! `unbounded::new_unbounded` and the zero-capacity `rendezvous::new_rendezvous`,
+//! and is used through the `SenderI`/`ReceiverI` traits. On linux,
+//! `shm::ShmSpsc` is an spsc queue shared between processes.
+//!
+//! The queues and their handles tell their state with `len`, `is_empty`,
+//! `is_full`, `capacity` and `is_disconnected`. These are SeqCst loads that
+//! neither lock (but for the rendezvous queue) nor wait, and other threads may
+//! change the state right after:
+//!
+//! * the elements seen by `len` were sent before it returned, a single
+//!   receiver can take at least that many without waiting, a single sender
+//!   can put at least `capacity() - len()` more
+//! * with several senders or receivers it is only an estimate, e.g. for
+//!   shedding load, `try_send`/`try_recv` tell for sure
+//! * `is_disconnected` stays true once true, the elements sent before can
+//!   still be received
+//!
+
+use std::time::{Duration, Instant};
+use snoozer::Snoozer;
+
+mod error;
+#[cfg(feature = "async")]
+pub mod future;
+#[cfg(target_os = "linux")]
+mod futex;
+mod snoozer;
+mod waker;
+pub mod spsc;
+pub mod mpsc;
+pub mod mpmc;
+pub mod lfmpmc;
+pub mod unbounded;
+pub mod rendezvous;
+#[cfg(target_os = "linux")]
+pub mod shm;
+pub mod select;
+#[cfg(feature = "stats")]
+pub mod stats;
+pub mod iter;
+
+pub use error::{SendError, RecvError, TrySendError, TryRecvError, SendTimeoutError, RecvTimeoutError};
+
+pub trait SenderI<T> {
+    fn send(&self, e :T) -> Result<(), SendError<T>>;
+    /// Send without waiting for room.
+    fn try_send(&self, e :T) -> Result<(), TrySendError<T>>;
+    /// Send, waiting for room until `deadline`.
+    fn send_deadline(&self, e :T, deadline: Instant) -> Result<(), SendTimeoutError<T>>;
+    /// Send, waiting for room at most `timeout`.
+    fn send_timeout(&self, e :T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.send_deadline(e, deadline),
+            None => self.send(e).map_err(SendTimeoutError::from),
+        }
+    }
+}
+pub trait ReceiverI<T> {
+    fn recv(&self) -> Result<T, RecvError>;
+    /// Receive without waiting for an element.
+    fn try_recv(&self) -> Result<T, TryRecvError>;
+    /// Receive, waiting for an element until `deadline`.
+    fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError>;
+    /// Receive, waiting for an element at most `timeout`.
+    fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.recv_deadline(deadline),
+            None => self.recv().map_err(RecvTimeoutError::from),
+        }
+    }
+}
+
+/// How a queue waits for room or elements.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitType {
+    /// query room or element with busy loop
+    BusyWait,
+    /// wait for room or element until be notified
+    SleepWait,
+    /// query with `spin_loop` hints for `spin_limit` rounds, then with
+    /// `thread::yield_now` until `yield_limit` rounds, then wait until be notified
+    Adaptive { spin_limit: u32, yield_limit: u32 },
+    /// wait for room or element on a futex until be notified, instead of a
+    /// Mutex and Condvar. `SpscQueue`, `MpscQueue`, `LfMpmcQueue` and `ShmSpsc` have
+    /// a futex path, `RendezvousQueue::new` panics on it. `MpmcQueue` takes no wait
+    /// mode and always waits on its Mutex and Condvar pairs
+    #[cfg(target_os = "linux")]
+    Futex,
+}
+
+impl WaitType {
+    /// `Adaptive` with the limits of the lock-free queue.
+    pub fn adaptive() -> WaitType {
+        WaitType::Adaptive { spin_limit: snoozer::SPIN_LIMIT, yield_limit: snoozer::YIELD_LIMIT }
+    }
+    /// Waiters may block on a Condvar, so they must be notified.
+    #[inline]
+    pub(crate) fn parks(self) -> bool {
+        !matches!(self, WaitType::BusyWait)
+    }
+    /// Snoozer with the limits of `Adaptive`, the defaults otherwise.
+    pub(crate) fn snoozer(self) -> Snoozer {
+        match self {
+            WaitType::Adaptive { spin_limit, yield_limit } => Snoozer::new(spin_limit, yield_limit),
+            _ => Snoozer::new(snoozer::SPIN_LIMIT, snoozer::YIELD_LIMIT),
+        }
+    }
+    /// The spin and yield phase of `Adaptive` before parking, ends early once
+    /// `ready` is true. Does nothing for the other modes.
+    pub(crate) fn snooze_until(self, mut ready: impl FnMut() -> bool) {
+        if let WaitType::Adaptive { .. } = self {
+            let mut snoozer = self.snoozer();
+            while !snoozer.completed() && !ready() {
+                snoozer.snooze();
+            }
+        }
+    }
+}
@@ -0,0 +1,920 @@
+//!
+//! rust mpmc queue, Mutex + Condvar
+//!
+
+use std::cell::Cell;
+use std::sync::{Mutex, MutexGuard, Condvar, Arc};
+use std::ptr;
+use std::alloc::Layout;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::task::Waker;
+use std::time::Instant;
+use crate::{SenderI, ReceiverI, SendError, RecvError,
+            TrySendError, TryRecvError, SendTimeoutError, RecvTimeoutError};
+use crate::iter::{Iter, TryIter, Drain, IntoIter};
+use crate::waker::WakerSet;
+use crate::select::SelectHandle;
+#[cfg(feature = "stats")]
+use crate::stats::{Stats, StatCounters};
+#[cfg(feature = "async")]
+use crate::future::{SendFuture, RecvFuture};
+
+pub struct Sender<T> {
+    inner : Arc<MpmcQueue<T>>,
+}
+impl<T> SenderI<T> for Sender<T> {
+    fn send(&self, e: T) -> Result<(), SendError<T>> {
+        self.inner.push(e)
+    }
+    fn try_send(&self, e: T) -> Result<(), TrySendError<T>> {
+        self.inner.try_push(e)
+    }
+    fn send_deadline(&self, e: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        self.inner.push_until(e, Some(deadline))
+    }
+}
+impl<T> Sender<T> {
+    /// See `MpmcQueue::len`.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+    /// See `MpmcQueue::is_empty`.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+    /// See `MpmcQueue::is_full`.
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+    /// See `MpmcQueue::capacity`.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See `MpmcQueue::is_disconnected`.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.is_disconnected()
+    }
+    /// Snapshot of the queue's counters, see `MpmcQueue::stats`.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+    /// See `MpmcQueue::send_batch`.
+    pub fn send_batch<I: IntoIterator<Item = T>>(&self, iter: I) -> Result<(), SendError<Vec<T>>> {
+        self.inner.send_batch(iter)
+    }
+    /// Send without blocking the thread, the task waits for room.
+    #[cfg(feature = "async")]
+    pub fn send_async(&self, e: T) -> SendFuture<'_, T, Self> {
+        SendFuture::new(self, &self.inner.room_wakers, e)
+    }
+}
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.inner.senders.fetch_add(1, Ordering::SeqCst);
+        Sender { inner: self.inner.clone() }
+    }
+}
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.inner.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.disconnect();
+        }
+    }
+}
+pub struct Receiver<T> {
+    inner: Arc<MpmcQueue<T>>,
+}
+impl<T> ReceiverI<T> for Receiver<T> {
+    fn recv(&self) -> Result<T, RecvError> {
+        self.inner.pop()
+    }
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.inner.try_pop()
+    }
+    fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.inner.pop_until(Some(deadline))
+    }
+}
+impl<T> Receiver<T> {
+    /// See `MpmcQueue::len`.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+    /// See `MpmcQueue::is_empty`.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+    /// See `MpmcQueue::is_full`.
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+    /// See `MpmcQueue::capacity`.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See `MpmcQueue::is_disconnected`.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.is_disconnected()
+    }
+    /// Snapshot of the queue's counters, see `MpmcQueue::stats`.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+    /// Iterate over the elements, waiting for each one, until the queue is
+    /// disconnected and drained.
+    pub fn iter(&self) -> Iter<'_, T, Self> {
+        Iter::new(self)
+    }
+    /// Iterate over the elements available without waiting.
+    pub fn try_iter(&self) -> TryIter<'_, T, Self> {
+        TryIter::new(self)
+    }
+    /// Iterate over at most the elements in the queue now.
+    pub fn drain(&self) -> Drain<'_, T, Self> {
+        Drain::new(self, self.inner.len())
+    }
+    /// See `MpmcQueue::recv_batch`.
+    pub fn recv_batch(&self, buf: &mut Vec<T>, max: usize) -> Result<usize, RecvError> {
+        self.inner.recv_batch(buf, max)
+    }
+    /// Receive without blocking the thread, the task waits for an element.
+    #[cfg(feature = "async")]
+    pub fn recv_async(&self) -> RecvFuture<'_, T, Self> {
+        RecvFuture::new(self, &self.inner.elem_wakers)
+    }
+}
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.inner.receivers.fetch_add(1, Ordering::SeqCst);
+        Receiver { inner: self.inner.clone() }
+    }
+}
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if self.inner.receivers.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.disconnect();
+        }
+    }
+}
+
+impl<T> SelectHandle for Sender<T> {
+    fn is_ready(&self) -> bool {
+        self.inner.count.load(Ordering::SeqCst) < self.inner.capacity || self.inner.is_disconnected()
+    }
+    fn register(&self, waker: &Waker) {
+        self.inner.room_wakers.register(waker);
+    }
+}
+impl<T> SelectHandle for Receiver<T> {
+    fn is_ready(&self) -> bool {
+        self.inner.count.load(Ordering::SeqCst) > 0 || self.inner.is_disconnected()
+    }
+    fn register(&self, waker: &Waker) {
+        self.inner.elem_wakers.register(waker);
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T, Self>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::new(self)
+    }
+}
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T, Receiver<T>>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub fn new_mpmc<T>(cap : usize) -> (Sender<T>, Receiver<T>) {
+    let qs = Arc::new(MpmcQueue::<T>::new(cap));
+    qs.senders.store(1, Ordering::SeqCst);
+    qs.receivers.store(1, Ordering::SeqCst);
+    let qr = qs.clone();
+    (Sender { inner: qs }, Receiver { inner: qr })
+}
+
+
+pub struct MpmcQueue<T> {
+    count: AtomicUsize,
+    _pad1: [i64; 7],
+    i_idx: Cell<usize>,
+    _pad2: [i64; 7],
+    o_idx: Cell<usize>,
+    _pad3: [i64; 7],
+    capacity: usize,
+    buf: *const T,
+    sem_room: (Mutex<()>, Condvar),
+    sem_elem: (Mutex<()>, Condvar),
+    room_wakers: WakerSet,
+    elem_wakers: WakerSet,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    disconnected: AtomicBool,
+    #[cfg(feature = "stats")]
+    stats: StatCounters,
+}
+
+impl<T> MpmcQueue<T> {
+    pub fn new(cap: usize) -> MpmcQueue<T> {
+        assert!(cap >= 1, "capacity too small, use rendezvous::new_rendezvous for capacity 0");
+
+        unsafe {
+            let layout = Layout::array::<T>(cap).expect("capacity too large");
+            let buf_size = layout.size();
+
+            // a ZST needs no storage, `count` alone tracks occupancy
+            let buf = if buf_size == 0 {
+                ptr::NonNull::<T>::dangling().as_ptr() as *mut u8
+            } else {
+                std::alloc::alloc(layout)
+            };
+            if buf.is_null() {
+                panic!("Out of memory")
+            }
+
+            MpmcQueue {
+                count: AtomicUsize::new(0),
+                _pad1: [0; 7],
+                i_idx: Cell::new(0),
+                _pad2: [0; 7],
+                o_idx: Cell::new(0),
+                _pad3: [0; 7],
+                capacity: cap,
+                buf: buf as *const T,
+                sem_room: (Mutex::new(()), Default::default()),
+                sem_elem: (Mutex::new(()), Default::default()),
+                room_wakers: WakerSet::new(),
+                elem_wakers: WakerSet::new(),
+                senders: AtomicUsize::new(0),
+                receivers: AtomicUsize::new(0),
+                disconnected: AtomicBool::new(false),
+                #[cfg(feature = "stats")]
+                stats: StatCounters::new(),
+            }
+        }
+    }
+
+    /// Wrap around at `capacity`, which needs not be a power of two.
+    #[inline]
+    fn next_idx(&self, idx: usize) -> usize {
+        if idx + 1 == self.capacity { 0 } else { idx + 1 }
+    }
+    #[inline]
+    fn put_elem(&self, e : T) {
+        unsafe {
+            ptr::write::<T>(self.buf.add(self.i_idx.get()) as *mut T, e);
+        }
+        self.i_idx.set(self.next_idx(self.i_idx.get()));
+    }
+    #[inline]
+    fn get_elem(&self) -> T {
+        let e = unsafe { ptr::read::<T>(self.buf.add(self.o_idx.get())) };
+        self.o_idx.set(self.next_idx(self.o_idx.get()));
+        e
+    }
+
+    /// Called when the last Sender or the last Receiver is dropped: wake up
+    /// all waiters of the other side, which then fail once they would have to wait.
+    fn disconnect(&self) {
+        self.disconnected.store(true, Ordering::SeqCst);
+        self.room_wakers.wake_all();
+        self.elem_wakers.wake_all();
+        {
+            let _g = self.sem_room.0.lock().unwrap();
+            self.sem_room.1.notify_all();
+        }
+        let _g = self.sem_elem.0.lock().unwrap();
+        self.sem_elem.1.notify_all();
+    }
+    /// Snapshot of the counters, see `stats::Stats`.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+    /// Number of elements in the queue, see the crate docs on how current it is.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity
+    }
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    /// All Senders or all Receivers are gone. Stays true once true,
+    /// elements sent before can still be received.
+    #[inline]
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::SeqCst)
+    }
+
+    pub fn push(&self, e: T) -> Result<(), SendError<T>> {
+        self.push_until(e, None).map_err(|err| match err {
+            SendTimeoutError::Disconnected(e) => SendError::Disconnected(e),
+            SendTimeoutError::Timeout(_) => unreachable!(),
+        })
+    }
+    /// Push, giving up at `deadline` if there is one.
+    fn push_until(&self, e: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        let mut g = self.sem_room.0.lock().unwrap();
+        while self.count.load(Ordering::SeqCst) == self.capacity {
+            if self.is_disconnected() {
+                return Err(SendTimeoutError::Disconnected(e));
+            }
+            #[cfg(feature = "stats")]
+            let _w = self.stats.send_wait();
+            g = match deadline {
+                None => self.sem_room.1.wait(g).unwrap(),
+                Some(d) => {
+                    let now = Instant::now();
+                    if now >= d {
+                        return Err(SendTimeoutError::Timeout(e));
+                    }
+                    self.sem_room.1.wait_timeout(g, d - now).unwrap().0
+                }
+            };
+        }
+        if self.is_disconnected() {
+            return Err(SendTimeoutError::Disconnected(e));
+        }
+        self.push_locked(g, e);
+        Ok(())
+    }
+    /// Put an element while holding the `sem_room` lock `g`.
+    fn push_locked(&self, g: MutexGuard<'_, ()>, e: T) {
+        self.put_elem(e);
+        self.publish_locked(g, 1);
+    }
+    /// Publish `n` elements put while holding the `sem_room` lock `g`, wake up the
+    /// next sender if there is still room and a receiver if the queue was empty.
+    fn publish_locked(&self, g: MutexGuard<'_, ()>, n: usize) {
+        let c = self.count.fetch_add(n, Ordering::SeqCst);
+        #[cfg(feature = "stats")]
+        self.stats.sent(n, c + n);
+        if c+n < self.capacity {
+            self.sem_room.1.notify_one();
+        }
+        drop(g);
+
+        if c == 0 {
+            self.elem_wakers.wake_all();
+            let _g = self.sem_elem.0.lock().unwrap();
+            self.sem_elem.1.notify_one();
+        }
+    }
+    pub fn pop(&self) -> Result<T, RecvError> {
+        self.pop_until(None).map_err(|_| RecvError::Disconnected)
+    }
+    /// Pop, giving up at `deadline` if there is one.
+    fn pop_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let mut g = self.sem_elem.0.lock().unwrap();
+        while self.count.load(Ordering::SeqCst) == 0 {
+            // senders are gone, checked after count so nothing sent is lost
+            if self.is_disconnected() && self.count.load(Ordering::SeqCst) == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            #[cfg(feature = "stats")]
+            let _w = self.stats.recv_wait();
+            g = match deadline {
+                None => self.sem_elem.1.wait(g).unwrap(),
+                Some(d) => {
+                    let now = Instant::now();
+                    if now >= d {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    self.sem_elem.1.wait_timeout(g, d - now).unwrap().0
+                }
+            };
+        }
+        Ok(self.pop_locked(g))
+    }
+    /// Get an element while holding the `sem_elem` lock `g`.
+    fn pop_locked(&self, g: MutexGuard<'_, ()>) -> T {
+        let e = self.get_elem();
+        self.consume_locked(g, 1);
+        e
+    }
+    /// Release `n` slots got while holding the `sem_elem` lock `g`, wake up the next
+    /// receiver if there are still elements and a sender if the queue was full.
+    fn consume_locked(&self, g: MutexGuard<'_, ()>, n: usize) {
+        let c = self.count.fetch_sub(n, Ordering::SeqCst);
+        #[cfg(feature = "stats")]
+        self.stats.received(n);
+        if c-n > 0 {
+            self.sem_elem.1.notify_one();
+        }
+        drop(g);
+
+        if c == self.capacity {
+            self.room_wakers.wake_all();
+            let _g = self.sem_room.0.lock().unwrap();
+            self.sem_room.1.notify_one();
+        }
+    }
+
+    pub fn try_push(&self, e: T) -> Result<(), TrySendError<T>> {
+        let g = self.sem_room.0.lock().unwrap();
+        if self.is_disconnected() {
+            return Err(TrySendError::Disconnected(e));
+        }
+        if self.count.load(Ordering::SeqCst) == self.capacity {
+            return Err(TrySendError::Full(e));
+        }
+        self.push_locked(g, e);
+        Ok(())
+    }
+    pub fn try_pop(&self) -> Result<T, TryRecvError> {
+        let g = self.sem_elem.0.lock().unwrap();
+        if self.count.load(Ordering::SeqCst) == 0 {
+            if self.is_disconnected() && self.count.load(Ordering::SeqCst) == 0 {
+                return Err(TryRecvError::Disconnected);
+            }
+            return Err(TryRecvError::Empty);
+        }
+        Ok(self.pop_locked(g))
+    }
+
+    /// Push all elements of `iter`, waiting for room like `push`. Each run of
+    /// free slots is published with one `count` update and one wakeup.
+    /// If the receivers are gone, the elements not sent are returned.
+    pub fn send_batch<I: IntoIterator<Item = T>>(&self, iter: I) -> Result<(), SendError<Vec<T>>> {
+        let mut iter = iter.into_iter().peekable();
+        while iter.peek().is_some() {
+            let mut g = self.sem_room.0.lock().unwrap();
+            while self.count.load(Ordering::SeqCst) == self.capacity && !self.is_disconnected() {
+                #[cfg(feature = "stats")]
+                let _w = self.stats.send_wait();
+                g = self.sem_room.1.wait(g).unwrap();
+            }
+            if self.is_disconnected() {
+                return Err(SendError::Disconnected(iter.collect()));
+            }
+            let room = self.capacity - self.count.load(Ordering::SeqCst);
+            let mut n = 0;
+            for e in iter.by_ref().take(room) {
+                self.put_elem(e);
+                n += 1;
+            }
+            self.publish_locked(g, n);
+        }
+        Ok(())
+    }
+
+    /// Wait for elements like `pop`, then move up to `max` of them to `buf`
+    /// with one `count` update and one wakeup. Returns the number received.
+    pub fn recv_batch(&self, buf: &mut Vec<T>, max: usize) -> Result<usize, RecvError> {
+        if max == 0 {
+            return Ok(0);
+        }
+        let mut g = self.sem_elem.0.lock().unwrap();
+        while self.count.load(Ordering::SeqCst) == 0 {
+            if self.is_disconnected() && self.count.load(Ordering::SeqCst) == 0 {
+                return Err(RecvError::Disconnected);
+            }
+            #[cfg(feature = "stats")]
+            let _w = self.stats.recv_wait();
+            g = self.sem_elem.1.wait(g).unwrap();
+        }
+        let n = self.count.load(Ordering::SeqCst).min(max);
+        buf.reserve(n);
+        for _ in 0..n {
+            buf.push(self.get_elem());
+        }
+        self.consume_locked(g, n);
+        Ok(n)
+    }
+}
+impl<T> Drop for MpmcQueue<T> {
+    fn drop(&mut self) {
+        // remove all elements
+        while self.count.load(Ordering::SeqCst) > 0 {
+            let _ = self.pop();
+        }
+
+        // free buffer
+        unsafe {
+            let layout = Layout::array::<T>(self.capacity).unwrap();
+            if layout.size() != 0 {
+                std::alloc::dealloc(self.buf as *mut u8, layout);
+            }
+        }
+    }
+}
+unsafe impl<T: Send> Send for MpmcQueue<T>{}
+unsafe impl<T: Send> Sync for MpmcQueue<T>{}
+
+impl<T> SenderI<T> for MpmcQueue<T> {
+    fn send(&self, e: T) -> Result<(), SendError<T>> {
+        self.push(e)
+    }
+    fn try_send(&self, e: T) -> Result<(), TrySendError<T>> {
+        self.try_push(e)
+    }
+    fn send_deadline(&self, e: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        self.push_until(e, Some(deadline))
+    }
+}
+impl<T> ReceiverI<T> for MpmcQueue<T> {
+    fn recv(&self) -> Result<T, RecvError> {
+        self.pop()
+    }
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.try_pop()
+    }
+    fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.pop_until(Some(deadline))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::mpmc::{MpmcQueue, new_mpmc};
+    use crate::{SenderI, ReceiverI, SendError, RecvError,
+                TrySendError, TryRecvError, SendTimeoutError, RecvTimeoutError};
+    use std::time::{Duration, Instant};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    fn send(w : &dyn SenderI<i64>) {
+        for i in 0..10 {
+            w.send(i as i64).unwrap();
+            println!("send {}", i);
+        }
+    }
+    fn recv(r : &dyn ReceiverI<i64>) {
+        for _ in 0..10 {
+            let e = r.recv().unwrap();
+            println!("--recv {}", e);
+        }
+    }
+
+    #[test]
+    fn test1() {
+        let q = MpmcQueue::<i64>::new(2<<5);
+        send(&q);
+        recv(&q);
+        // drop(q);
+
+        let q = Arc::new(q);
+        send(&*q);
+        recv(&*q);
+
+        let qc = q.clone();
+        let t = thread::spawn(move ||{ send(&*qc);});
+        recv(&*q);
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test2(){
+        let (wr, rd) = new_mpmc::<i64>(2<<6);
+        let t1 = thread::spawn(move || {recv(&rd);});
+        let t2 = thread::spawn(move || {send(&wr);});
+        t1.join().unwrap();
+        t2.join().unwrap();
+    }
+
+    #[test]
+    fn test_disconnect() {
+        // receivers drain what's left, then get Disconnected
+        let (wr, rd) = new_mpmc::<i64>(2<<6);
+        let wr2 = wr.clone();
+        let t1 = thread::spawn(move || {send(&wr);});
+        let t2 = thread::spawn(move || {send(&wr2);});
+        t1.join().unwrap();
+        t2.join().unwrap();
+        let rd2 = rd.clone();
+        recv(&rd);
+        recv(&rd2);
+        assert_eq!(rd.recv(), Err(RecvError::Disconnected));
+        assert_eq!(rd2.recv(), Err(RecvError::Disconnected));
+
+        // all blocked receivers are woken up
+        let (wr, rd) = new_mpmc::<i64>(4);
+        let ts: Vec<_> = (0..3).map(|_| {
+            let rd = rd.clone();
+            thread::spawn(move || rd.recv())
+        }).collect();
+        thread::sleep(std::time::Duration::from_millis(50));
+        drop(wr);
+        for t in ts {
+            assert_eq!(t.join().unwrap(), Err(RecvError::Disconnected));
+        }
+
+        // sends fail once every receiver is gone
+        let (wr, rd) = new_mpmc::<i64>(4);
+        let rd2 = rd.clone();
+        drop(rd);
+        wr.send(1).unwrap();
+        drop(rd2);
+        assert_eq!(wr.send(2), Err(SendError::Disconnected(2)));
+    }
+
+    #[test]
+    fn test_try() {
+        let (wr, rd) = new_mpmc::<i64>(2);
+        assert_eq!(rd.try_recv(), Err(TryRecvError::Empty));
+        wr.try_send(1).unwrap();
+        wr.try_send(2).unwrap();
+        assert_eq!(wr.try_send(3), Err(TrySendError::Full(3)));
+        assert_eq!(rd.try_recv(), Ok(1));
+        wr.try_send(3).unwrap();
+        drop(wr);
+        assert_eq!(rd.try_recv(), Ok(2));
+        assert_eq!(rd.try_recv(), Ok(3));
+        assert_eq!(rd.try_recv(), Err(TryRecvError::Disconnected));
+
+        let (wr, rd) = new_mpmc::<i64>(2);
+        drop(rd);
+        assert_eq!(wr.try_send(1), Err(TrySendError::Disconnected(1)));
+
+        // try_send wakes up a blocked receiver
+        let (wr, rd) = new_mpmc::<i64>(1);
+        let t = thread::spawn(move || rd.recv());
+        thread::sleep(std::time::Duration::from_millis(50));
+        wr.try_send(1).unwrap();
+        assert_eq!(t.join().unwrap(), Ok(1));
+    }
+
+    #[test]
+    fn test_capacity() {
+        for cap in [1, 3, 5, 1000] {
+            // exactly cap elements fit, and come out in order
+            let (wr, rd) = new_mpmc::<usize>(cap);
+            for round in 0..3 {
+                for i in 0..cap {
+                    wr.try_send(round * cap + i).unwrap();
+                }
+                assert_eq!(wr.try_send(0), Err(TrySendError::Full(0)));
+                for i in 0..cap {
+                    assert_eq!(rd.try_recv(), Ok(round * cap + i));
+                }
+                assert_eq!(rd.try_recv(), Err(TryRecvError::Empty));
+            }
+
+            // wrap around many times with several senders and receivers
+            let n = cap * 7 + 3;
+            let ts: Vec<_> = (0..3).map(|_| {
+                let wr = wr.clone();
+                thread::spawn(move || {
+                    for i in 0..n {
+                        wr.send(i).unwrap();
+                    }
+                })
+            }).collect();
+            drop(wr);
+            let rs: Vec<_> = (0..2).map(|_| {
+                let rd = rd.clone();
+                thread::spawn(move || {
+                    let mut sum = 0;
+                    while let Ok(e) = rd.recv() {
+                        sum += e;
+                    }
+                    sum
+                })
+            }).collect();
+            for t in ts {
+                t.join().unwrap();
+            }
+            let sum: usize = rs.into_iter().map(|t| t.join().unwrap()).sum();
+            assert_eq!(sum, 3 * (0..n).sum::<usize>());
+        }
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (wr, rd) = new_mpmc::<i64>(1);
+        let begin = Instant::now();
+        assert_eq!(rd.recv_timeout(Duration::from_millis(20)), Err(RecvTimeoutError::Timeout));
+        assert!(begin.elapsed() >= Duration::from_millis(20));
+
+        wr.send_timeout(1, Duration::from_millis(20)).unwrap();
+        let begin = Instant::now();
+        assert_eq!(wr.send_timeout(2, Duration::from_millis(20)), Err(SendTimeoutError::Timeout(2)));
+        assert!(begin.elapsed() >= Duration::from_millis(20));
+        assert_eq!(wr.send_deadline(2, Instant::now()), Err(SendTimeoutError::Timeout(2)));
+
+        assert_eq!(rd.recv_deadline(Instant::now() + Duration::from_millis(20)), Ok(1));
+        assert_eq!(rd.recv_deadline(Instant::now()), Err(RecvTimeoutError::Timeout));
+
+        // a value sent in time is received
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            wr.send(3).unwrap();
+            wr
+        });
+        assert_eq!(rd.recv_timeout(Duration::from_secs(10)), Ok(3));
+        drop(t.join().unwrap());
+        assert_eq!(rd.recv_timeout(Duration::from_secs(10)), Err(RecvTimeoutError::Disconnected));
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity too large")]
+    fn test_capacity_overflow() {
+        // the buffer size overflows usize
+        let _ = new_mpmc::<u64>(usize::MAX / 4);
+    }
+
+    #[test]
+    fn test_zst() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Token;
+        impl Drop for Token {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let (wr, rd) = new_mpmc::<Token>(4);
+        for _ in 0..4 {
+            assert!(wr.try_send(Token).is_ok());
+        }
+        assert!(wr.try_send(Token).is_err());
+        assert!(rd.recv().is_ok());
+        assert_eq!(DROPS.load(Ordering::SeqCst), 2);
+        // the queue drops the remaining elements
+        drop(wr);
+        drop(rd);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn test_batch() {
+        let (wr, rd) = new_mpmc::<usize>(5);
+        let n = 1000;
+        let ts: Vec<_> = (0..2).map(|_| {
+            let wr = wr.clone();
+            thread::spawn(move || {
+                for i in (0..n).step_by(10) {
+                    wr.send_batch(i..i + 10).unwrap();
+                }
+            })
+        }).collect();
+        drop(wr);
+        let rs: Vec<_> = (0..2).map(|_| {
+            let rd = rd.clone();
+            thread::spawn(move || {
+                let mut v = Vec::new();
+                while rd.recv_batch(&mut v, 3).is_ok() {}
+                v.iter().sum::<usize>()
+            })
+        }).collect();
+        for t in ts {
+            t.join().unwrap();
+        }
+        let sum: usize = rs.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(sum, 2 * (0..n).sum::<usize>());
+
+        // elements not sent are returned
+        let (wr, rd) = new_mpmc::<usize>(3);
+        let t = thread::spawn(move || wr.send_batch(0..5));
+        let mut v = Vec::new();
+        assert_eq!(rd.recv_batch(&mut v, 1), Ok(1));
+        assert_eq!(v, [0]);
+        drop(rd);
+        let rest = match t.join().unwrap() {
+            Err(SendError::Disconnected(rest)) => rest,
+            Ok(()) => panic!("sent to a disconnected queue"),
+        };
+        assert!(!rest.is_empty() && rest.last() == Some(&4));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async() {
+        use crate::future::block_on;
+        use std::future::Future;
+        use std::task::{Context, Poll, Waker};
+
+        let (wr, rd) = new_mpmc::<usize>(3);
+        let ts: Vec<_> = (0..2).map(|_| {
+            let wr = wr.clone();
+            thread::spawn(move || block_on(async {
+                for i in 0..1000 {
+                    wr.send_async(i).await.unwrap();
+                }
+            }))
+        }).collect();
+        drop(wr);
+        let rs: Vec<_> = (0..2).map(|_| {
+            let rd = rd.clone();
+            thread::spawn(move || block_on(async {
+                let mut sum = 0;
+                while let Ok(e) = rd.recv_async().await {
+                    sum += e;
+                }
+                sum
+            }))
+        }).collect();
+        for t in ts {
+            t.join().unwrap();
+        }
+        let sum: usize = rs.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(sum, 2 * (0..1000).sum::<usize>());
+
+        // both tasks polled by turns on one thread
+        let (wr, rd) = new_mpmc::<usize>(1);
+        let mut send = Box::pin(async {
+            for i in 0..10 {
+                wr.send_async(i).await.unwrap();
+            }
+        });
+        let mut recv = Box::pin(async {
+            let mut v = Vec::new();
+            for _ in 0..10 {
+                v.push(rd.recv_async().await.unwrap());
+            }
+            v
+        });
+        let mut cx = Context::from_waker(Waker::noop());
+        let v = loop {
+            let _ = send.as_mut().poll(&mut cx);
+            if let Poll::Ready(v) = recv.as_mut().poll(&mut cx) {
+                break v;
+            }
+        };
+        assert_eq!(v, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_iter() {
+        let (wr, rd) = new_mpmc::<usize>(4);
+        let rs: Vec<_> = (0..2).map(|_| {
+            let rd = rd.clone();
+            thread::spawn(move || rd.into_iter().filter(|e| e % 2 == 0).count())
+        }).collect();
+        drop(rd);
+        for i in 0..1000 {
+            wr.send(i).unwrap();
+        }
+        drop(wr);
+        let n: usize = rs.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(n, 500);
+
+        // drain stops even if the sender keeps sending
+        let (wr, rd) = new_mpmc::<usize>(4);
+        wr.send(0).unwrap();
+        wr.send(1).unwrap();
+        let mut n = 0;
+        for e in rd.drain() {
+            let _ = wr.try_send(e + 2);
+            n += 1;
+        }
+        assert_eq!(n, 2);
+        assert_eq!((&rd).into_iter().take(2).collect::<Vec<_>>(), [2, 3]);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn test_stats() {
+        let (wr, rd) = new_mpmc::<i64>(4);
+        wr.send_batch(0..4).unwrap();
+        let wr2 = wr.clone();
+        let t = thread::spawn(move || {
+            // the queue is full, the sender waits
+            wr2.send(4).unwrap();
+        });
+        while wr.stats().waiting_senders == 0 {
+            thread::yield_now();
+        }
+        let mut v = Vec::new();
+        assert_eq!(rd.recv_batch(&mut v, 8), Ok(4));
+        t.join().unwrap();
+        assert_eq!(rd.recv(), Ok(4));
+
+        let s = rd.stats();
+        assert_eq!((s.sends, s.recvs, s.peak_len), (5, 5, 4));
+        assert!(s.send_waits >= 1);
+        assert_eq!(s.waiting_senders, 0);
+        assert_eq!(s.spins, 0);
+    }
+
+    #[test]
+    fn test_len() {
+        let (wr, rd) = new_mpmc::<i64>(2);
+        let wr2 = wr.clone();
+        assert_eq!((wr.len(), wr.capacity()), (0, 2));
+        wr.send(1).unwrap();
+        wr2.send(2).unwrap();
+        assert!(rd.is_full() && !rd.is_empty());
+        drop(wr);
+        // a sender is left
+        assert!(!rd.is_disconnected());
+        drop(wr2);
+        assert!(rd.is_disconnected());
+        assert_eq!(rd.len(), 2);
+    }
+}
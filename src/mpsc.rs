@@ -0,0 +1,758 @@
+//!
+//! rust mpsc queue
+//!
+//! Senders are serialized by the `sem_room` lock, like `MpmcQueue`.
+//! The only receiver reads like `SpscQueue`: it never takes the `sem_elem`
+//! lock unless it has to sleep for an element.
+//! In BusyWait and Futex mode a sender waits for room without the lock, spinning
+//! or sleeping on `futex_room`, and takes it once there is room.
+//!
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::sync::{Mutex, MutexGuard, Condvar, Arc};
+use std::ptr;
+use std::alloc::Layout;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Instant;
+use crate::{SenderI, ReceiverI, WaitType, SendError, RecvError,
+            TrySendError, TryRecvError, SendTimeoutError, RecvTimeoutError};
+use crate::iter::{Iter, TryIter, Drain, IntoIter};
+#[cfg(target_os = "linux")]
+use crate::futex::FutexSem;
+
+pub struct Sender<T> {
+    inner : Arc<MpscQueue<T>>,
+}
+impl<T> SenderI<T> for Sender<T> {
+    fn send(&self, e: T) -> Result<(), SendError<T>> {
+        self.inner.push(e)
+    }
+    fn try_send(&self, e: T) -> Result<(), TrySendError<T>> {
+        self.inner.try_push(e)
+    }
+    fn send_deadline(&self, e: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        self.inner.push_until(e, Some(deadline))
+    }
+}
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.inner.senders.fetch_add(1, Ordering::SeqCst);
+        Sender { inner: self.inner.clone() }
+    }
+}
+impl<T> Sender<T> {
+    /// See `MpscQueue::len`.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+    /// See `MpscQueue::is_empty`.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+    /// See `MpscQueue::is_full`.
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+    /// See `MpscQueue::capacity`.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See `MpscQueue::is_disconnected`.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.is_disconnected()
+    }
+}
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.inner.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.disconnect();
+        }
+    }
+}
+/// The only receiving half. It can be sent to another thread but not shared:
+///
+/// ```compile_fail
+/// fn shared<R: Sync>() {}
+/// shared::<queue::mpsc::Receiver<i64>>();
+/// ```
+pub struct Receiver<T> {
+    inner: Arc<MpscQueue<T>>,
+    /// `pop` moves `o_idx` without a lock, so only one thread may receive
+    _not_sync: PhantomData<Cell<()>>,
+}
+impl<T> ReceiverI<T> for Receiver<T> {
+    fn recv(&self) -> Result<T, RecvError> {
+        self.inner.pop()
+    }
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.inner.try_pop()
+    }
+    fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.inner.pop_until(Some(deadline))
+    }
+}
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.disconnect();
+    }
+}
+
+impl<T> Receiver<T> {
+    /// See `MpscQueue::len`.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+    /// See `MpscQueue::is_empty`.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+    /// See `MpscQueue::is_full`.
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+    /// See `MpscQueue::capacity`.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See `MpscQueue::is_disconnected`.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.is_disconnected()
+    }
+    /// Iterate over the elements, waiting for each one, until the queue is
+    /// disconnected and drained.
+    pub fn iter(&self) -> Iter<'_, T, Self> {
+        Iter::new(self)
+    }
+    /// Iterate over the elements available without waiting.
+    pub fn try_iter(&self) -> TryIter<'_, T, Self> {
+        TryIter::new(self)
+    }
+    /// Iterate over at most the elements in the queue now.
+    pub fn drain(&self) -> Drain<'_, T, Self> {
+        Drain::new(self, self.inner.len())
+    }
+}
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T, Self>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::new(self)
+    }
+}
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T, Receiver<T>>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub fn new_mpsc<T>(cap : usize, wait_mode : WaitType) -> (Sender<T>, Receiver<T>) {
+    let qs = Arc::new(MpscQueue::<T>::new(cap, wait_mode));
+    qs.senders.store(1, Ordering::SeqCst);
+    let qr = qs.clone();
+    (Sender { inner: qs }, Receiver { inner: qr, _not_sync: PhantomData })
+}
+
+pub struct MpscQueue<T> {
+    count: AtomicUsize,
+    _pad1: [i64; 7],
+    i_idx: Cell<usize>,
+    /// the receiver sleeps on it in Futex mode
+    #[cfg(target_os = "linux")]
+    futex_elem: FutexSem,
+    _pad2: [i64; 7],
+    o_idx: Cell<usize>,
+    /// the senders sleep on it in Futex mode
+    #[cfg(target_os = "linux")]
+    futex_room: FutexSem,
+    _pad3: [i64; 7],
+    capacity: usize,
+    wait_mode: WaitType,
+    buf: *const T,
+    sem_room: (Mutex<()>, Condvar),
+    sem_elem: (Mutex<()>, Condvar),
+    senders: AtomicUsize,
+    disconnected: AtomicBool,
+}
+
+impl<T> MpscQueue<T> {
+    pub fn new(cap: usize, wait_mode: WaitType) -> MpscQueue<T> {
+        assert!(cap >= 1, "capacity too small");
+
+        unsafe {
+            let layout = Layout::array::<T>(cap).expect("capacity too large");
+            let buf_size = layout.size();
+
+            // a ZST needs no storage, `count` alone tracks occupancy
+            let buf = if buf_size == 0 {
+                ptr::NonNull::<T>::dangling().as_ptr() as *mut u8
+            } else {
+                std::alloc::alloc(layout)
+            };
+            if buf.is_null() {
+                panic!("Out of memory")
+            }
+
+            MpscQueue {
+                count: AtomicUsize::new(0),
+                _pad1: [0; 7],
+                i_idx: Cell::new(0),
+                #[cfg(target_os = "linux")]
+                futex_elem: FutexSem::new(),
+                _pad2: [0; 7],
+                o_idx: Cell::new(0),
+                #[cfg(target_os = "linux")]
+                futex_room: FutexSem::new(),
+                _pad3: [0; 7],
+                capacity: cap,
+                buf: buf as *const T,
+                wait_mode,
+                sem_room: (Mutex::new(()), Default::default()),
+                sem_elem: (Mutex::new(()), Default::default()),
+                senders: AtomicUsize::new(0),
+                disconnected: AtomicBool::new(false),
+            }
+        }
+    }
+
+    /// Wrap around at `capacity`, which needs not be a power of two.
+    #[inline]
+    fn next_idx(&self, idx: usize) -> usize {
+        if idx + 1 == self.capacity { 0 } else { idx + 1 }
+    }
+    fn put_elem(&self, e : T) {
+        unsafe {
+            ptr::write::<T>(self.buf.add(self.i_idx.get()) as *mut T, e);
+        }
+        self.i_idx.set(self.next_idx(self.i_idx.get()));
+    }
+    fn get_elem(&self) -> T {
+        let e = unsafe { ptr::read::<T>(self.buf.add(self.o_idx.get())) };
+        self.o_idx.set(self.next_idx(self.o_idx.get()));
+        e
+    }
+
+    /// Called when the last Sender or the Receiver is dropped: wake up
+    /// all waiters of the other side, which then fail once they would have to wait.
+    fn disconnect(&self) {
+        self.disconnected.store(true, Ordering::SeqCst);
+        #[cfg(target_os = "linux")]
+        {
+            self.futex_room.wake();
+            self.futex_elem.wake();
+        }
+        {
+            let _g = self.sem_room.0.lock().unwrap();
+            self.sem_room.1.notify_all();
+        }
+        let _g = self.sem_elem.0.lock().unwrap();
+        self.sem_elem.1.notify_all();
+    }
+    /// Number of elements in the queue, see the crate docs on how current it is.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity
+    }
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    /// All Senders or the Receiver are gone. Stays true once true,
+    /// elements sent before can still be received.
+    #[inline]
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::SeqCst)
+    }
+    /// The senders are gone. Checked after `count`, so elements sent before
+    /// the disconnect are still visible here.
+    #[inline]
+    fn is_drained(&self) -> bool {
+        self.is_disconnected() && self.count.load(Ordering::SeqCst) == 0
+    }
+    /// BusyWait and Futex senders wait for room without the `sem_room` lock.
+    #[inline]
+    fn waits_unlocked(&self) -> bool {
+        match self.wait_mode {
+            WaitType::BusyWait => true,
+            #[cfg(target_os = "linux")]
+            WaitType::Futex => true,
+            _ => false,
+        }
+    }
+    /// `None` or not yet expired.
+    #[inline]
+    fn before(deadline: Option<Instant>) -> bool {
+        match deadline {
+            Some(d) => Instant::now() < d,
+            None => true,
+        }
+    }
+
+    #[inline]
+    pub fn push(&self, e : T) -> Result<(), SendError<T>> {
+        self.push_until(e, None).map_err(|err| match err {
+            SendTimeoutError::Disconnected(e) => SendError::Disconnected(e),
+            SendTimeoutError::Timeout(_) => unreachable!(),
+        })
+    }
+
+    /// Only for the `Receiver`, which is not Sync.
+    #[inline]
+    pub(crate) fn pop(&self) -> Result<T, RecvError> {
+        self.pop_until(None).map_err(|_| RecvError::Disconnected)
+    }
+
+    /// Push, giving up at `deadline` if there is one.
+    fn push_until(&self, e : T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        let full = || self.count.load(Ordering::SeqCst) == self.capacity;
+        let mut g = if self.waits_unlocked() {
+            // wait without the lock, so that `try_push` and the other senders
+            // are not held up, and take it once there is room
+            loop {
+                while full() {
+                    if self.is_disconnected() {
+                        return Err(SendTimeoutError::Disconnected(e));
+                    }
+                    if !Self::before(deadline) {
+                        return Err(SendTimeoutError::Timeout(e));
+                    }
+                    match self.wait_mode {
+                        #[cfg(target_os = "linux")]
+                        WaitType::Futex => self.futex_room.wait(|| full() && !self.is_disconnected(), deadline),
+                        _ => std::hint::spin_loop(),
+                    }
+                }
+                let g = self.sem_room.0.lock().unwrap();
+                // another sender may have taken the room, only the receiver frees it
+                if !full() {
+                    break g;
+                }
+            }
+        } else {
+            self.wait_mode.snooze_until(|| !full() || self.is_disconnected());
+            self.sem_room.0.lock().unwrap()
+        };
+        while full() {
+            if self.is_disconnected() {
+                return Err(SendTimeoutError::Disconnected(e));
+            }
+            g = match deadline {
+                None => self.sem_room.1.wait(g).unwrap(),
+                Some(d) => {
+                    let now = Instant::now();
+                    if now >= d {
+                        return Err(SendTimeoutError::Timeout(e));
+                    }
+                    self.sem_room.1.wait_timeout(g, d - now).unwrap().0
+                }
+            };
+        }
+        if self.is_disconnected() {
+            return Err(SendTimeoutError::Disconnected(e));
+        }
+        self.push_locked(g, e);
+        Ok(())
+    }
+    /// Put an element while holding the `sem_room` lock `g`. Unless in BusyWait mode
+    /// wake up the next sender if there is still room and the receiver if the
+    /// queue was empty.
+    fn push_locked(&self, g: MutexGuard<'_, ()>, e: T) {
+        self.put_elem(e);
+        let c = self.count.fetch_add(1, Ordering::SeqCst);
+        match self.wait_mode {
+            WaitType::BusyWait => {}
+            #[cfg(target_os = "linux")]
+            WaitType::Futex => {
+                drop(g);
+                if c == 0 {
+                    self.futex_elem.wake();
+                }
+            }
+            _ => {
+                if c+1 < self.capacity {
+                    self.sem_room.1.notify_one();
+                }
+                drop(g);
+
+                if c == 0 {
+                    let _g = self.sem_elem.0.lock().unwrap();
+                    self.sem_elem.1.notify_one();
+                }
+            }
+        }
+    }
+
+    /// Pop, giving up at `deadline` if there is one.
+    fn pop_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        if self.count.load(Ordering::SeqCst) == 0 {
+            match self.wait_mode {
+                WaitType::BusyWait => {
+                    while self.count.load(Ordering::SeqCst) == 0 {
+                        if self.is_drained() {
+                            return Err(RecvTimeoutError::Disconnected);
+                        }
+                        if !Self::before(deadline) {
+                            return Err(RecvTimeoutError::Timeout);
+                        }
+                    }
+                }
+                #[cfg(target_os = "linux")]
+                WaitType::Futex => {
+                    let empty = || self.count.load(Ordering::SeqCst) == 0;
+                    while empty() {
+                        if self.is_drained() {
+                            return Err(RecvTimeoutError::Disconnected);
+                        }
+                        if !Self::before(deadline) {
+                            return Err(RecvTimeoutError::Timeout);
+                        }
+                        self.futex_elem.wait(|| empty() && !self.is_disconnected(), deadline);
+                    }
+                }
+                _ => {
+                    self.wait_mode.snooze_until(|| {
+                        self.count.load(Ordering::SeqCst) > 0 || self.is_disconnected()
+                    });
+                    let mut g = self.sem_elem.0.lock().unwrap();
+                    while self.count.load(Ordering::SeqCst) == 0 {
+                        if self.is_drained() {
+                            return Err(RecvTimeoutError::Disconnected);
+                        }
+                        g = match deadline {
+                            None => self.sem_elem.1.wait(g).unwrap(),
+                            Some(d) => {
+                                let now = Instant::now();
+                                if now >= d {
+                                    return Err(RecvTimeoutError::Timeout);
+                                }
+                                self.sem_elem.1.wait_timeout(g, d - now).unwrap().0
+                            }
+                        };
+                    }
+                }
+            }
+        }
+        Ok(self.pop_elem())
+    }
+    /// Get an element, the queue is not empty. Unless in BusyWait mode wake up a
+    /// sender if the queue was full.
+    fn pop_elem(&self) -> T {
+        let e = self.get_elem();
+        let c = self.count.fetch_sub(1, Ordering::SeqCst);
+        if c == self.capacity {
+            match self.wait_mode {
+                WaitType::BusyWait => {}
+                #[cfg(target_os = "linux")]
+                WaitType::Futex => self.futex_room.wake(),
+                _ => {
+                    let _g = self.sem_room.0.lock().unwrap();
+                    self.sem_room.1.notify_one();
+                }
+            }
+        }
+        e
+    }
+
+    pub fn try_push(&self, e: T) -> Result<(), TrySendError<T>> {
+        let g = self.sem_room.0.lock().unwrap();
+        if self.is_disconnected() {
+            return Err(TrySendError::Disconnected(e));
+        }
+        if self.count.load(Ordering::SeqCst) == self.capacity {
+            return Err(TrySendError::Full(e));
+        }
+        self.push_locked(g, e);
+        Ok(())
+    }
+    /// Only for the `Receiver`, which is not Sync.
+    pub(crate) fn try_pop(&self) -> Result<T, TryRecvError> {
+        if self.count.load(Ordering::SeqCst) == 0 {
+            if self.is_drained() {
+                return Err(TryRecvError::Disconnected);
+            }
+            return Err(TryRecvError::Empty);
+        }
+        Ok(self.pop_elem())
+    }
+}
+impl<T> Drop for MpscQueue<T> {
+    fn drop(&mut self) {
+        // remove all elements
+        while self.count.load(Ordering::SeqCst) > 0 {
+            let _ = self.pop_elem();
+        }
+
+        // free buffer
+        unsafe {
+            let layout = Layout::array::<T>(self.capacity).unwrap();
+            if layout.size() != 0 {
+                std::alloc::dealloc(self.buf as *mut u8, layout);
+            }
+        }
+    }
+}
+unsafe impl<T: Send> Send for MpscQueue<T>{}
+unsafe impl<T: Send> Sync for MpscQueue<T>{}
+
+#[cfg(test)]
+mod tests{
+    use crate::mpsc::new_mpsc;
+    use crate::{SenderI, ReceiverI, WaitType, SendError, RecvError,
+                TrySendError, TryRecvError, SendTimeoutError, RecvTimeoutError};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test1() {
+        for cap in [1, 3, 64] {
+            for wait_mode in [WaitType::BusyWait, WaitType::SleepWait, WaitType::adaptive()] {
+                let (wr, rd) = new_mpsc::<i64>(cap, wait_mode);
+                let ts: Vec<_> = (0..4).map(|k| {
+                    let wr = wr.clone();
+                    thread::spawn(move || {
+                        for i in 0..100 {
+                            wr.send(k * 1000 + i).unwrap();
+                        }
+                    })
+                }).collect();
+                drop(wr);
+
+                // every sender's elements come out in its own order
+                let mut last = [-1i64; 4];
+                let mut n = 0;
+                while let Ok(e) = rd.recv() {
+                    let k = (e / 1000) as usize;
+                    assert!(e > last[k]);
+                    last[k] = e;
+                    n += 1;
+                }
+                assert_eq!(n, 400);
+                for t in ts {
+                    t.join().unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_try() {
+        let (wr, rd) = new_mpsc::<i64>(2, WaitType::SleepWait);
+        let wr2 = wr.clone();
+        assert_eq!(rd.try_recv(), Err(TryRecvError::Empty));
+        wr.try_send(1).unwrap();
+        wr2.try_send(2).unwrap();
+        assert_eq!(wr.try_send(3), Err(TrySendError::Full(3)));
+        assert_eq!(rd.try_recv(), Ok(1));
+        drop(wr);
+        wr2.try_send(3).unwrap();
+        drop(wr2);
+        assert_eq!(rd.try_recv(), Ok(2));
+        assert_eq!(rd.recv(), Ok(3));
+        assert_eq!(rd.try_recv(), Err(TryRecvError::Disconnected));
+        assert_eq!(rd.recv(), Err(RecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_disconnect() {
+        for wait_mode in [WaitType::BusyWait, WaitType::SleepWait, WaitType::adaptive()] {
+            // a blocked sender gets its element back once the receiver is gone
+            let (wr, rd) = new_mpsc::<i64>(1, wait_mode);
+            wr.send(1).unwrap();
+            let wr2 = wr.clone();
+            let t = thread::spawn(move || wr2.send(2));
+            thread::sleep(Duration::from_millis(50));
+            drop(rd);
+            assert_eq!(t.join().unwrap(), Err(SendError::Disconnected(2)));
+            assert_eq!(wr.send(3), Err(SendError::Disconnected(3)));
+
+            let (wr, rd) = new_mpsc::<i64>(1, wait_mode);
+            assert_eq!(rd.recv_timeout(Duration::from_millis(20)), Err(RecvTimeoutError::Timeout));
+            drop(wr);
+            assert_eq!(rd.recv_timeout(Duration::from_secs(10)), Err(RecvTimeoutError::Disconnected));
+        }
+    }
+
+    #[test]
+    fn test_busy_full() {
+        #[allow(unused_mut)]
+        let mut modes = vec![WaitType::BusyWait];
+        #[cfg(target_os = "linux")]
+        modes.push(WaitType::Futex);
+        for mode in modes {
+            // a sender waiting on a full queue must not hold up the others
+            let (wr, rd) = new_mpsc::<i64>(1, mode);
+            wr.send(1).unwrap();
+            let ts: Vec<_> = (0..2).map(|k| {
+                let wr = wr.clone();
+                thread::spawn(move || wr.send(10 + k))
+            }).collect();
+            thread::sleep(Duration::from_millis(50));
+            for i in 0..3 {
+                let begin = Instant::now();
+                assert_eq!(wr.try_send(i), Err(TrySendError::Full(i)));
+                assert_eq!(wr.send_timeout(i, Duration::from_millis(20)), Err(SendTimeoutError::Timeout(i)));
+                assert!(begin.elapsed() < Duration::from_secs(1));
+            }
+            let mut got: Vec<_> = (0..3).map(|_| rd.recv().unwrap()).collect();
+            for t in ts {
+                t.join().unwrap().unwrap();
+            }
+            got.sort();
+            assert_eq!(got, vec![1, 10, 11]);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_futex() {
+        for &cap in &[1, 5] {
+            let (wr, rd) = new_mpsc::<i64>(cap, WaitType::Futex);
+            let ts: Vec<_> = (0..4).map(|k| {
+                let wr = wr.clone();
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        wr.send(k * 10000 + i).unwrap();
+                    }
+                })
+            }).collect();
+            drop(wr);
+            let mut last = [-1i64; 4];
+            let mut n = 0;
+            while let Ok(e) = rd.recv() {
+                let k = (e / 10000) as usize;
+                assert!(e > last[k]);
+                last[k] = e;
+                n += 1;
+            }
+            assert_eq!(n, 4000);
+            for t in ts {
+                t.join().unwrap();
+            }
+        }
+
+        let (wr, rd) = new_mpsc::<i64>(1, WaitType::Futex);
+        assert_eq!(rd.recv_timeout(Duration::from_millis(20)), Err(RecvTimeoutError::Timeout));
+        wr.send(1).unwrap();
+        let wr2 = wr.clone();
+        let t = thread::spawn(move || wr2.send(2));
+        thread::sleep(Duration::from_millis(50));
+        drop(rd);
+        assert_eq!(t.join().unwrap(), Err(SendError::Disconnected(2)));
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity too large")]
+    fn test_capacity_overflow() {
+        // the buffer size overflows usize
+        let _ = new_mpsc::<u64>(usize::MAX / 4, WaitType::SleepWait);
+    }
+
+    #[test]
+    fn test_zst() {
+        for &mode in &[WaitType::BusyWait, WaitType::SleepWait, WaitType::adaptive()] {
+            let (wr, rd) = new_mpsc::<()>(2, mode);
+            let ts: Vec<_> = (0..4).map(|_| {
+                let wr = wr.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        wr.send(()).unwrap();
+                    }
+                })
+            }).collect();
+            drop(wr);
+            let mut n = 0;
+            while rd.recv().is_ok() {
+                n += 1;
+            }
+            for t in ts {
+                t.join().unwrap();
+            }
+            assert_eq!(n, 400);
+        }
+    }
+
+    #[test]
+    fn test_iter() {
+        // iter ends once every sender is gone
+        let (wr, rd) = new_mpsc::<usize>(4, WaitType::SleepWait);
+        let ts: Vec<_> = (0..2).map(|k| {
+            let wr = wr.clone();
+            thread::spawn(move || {
+                for i in 0..500 {
+                    wr.send(k * 500 + i).unwrap();
+                }
+            })
+        }).collect();
+        drop(wr);
+        assert_eq!(rd.iter().count(), 1000);
+        for t in ts {
+            t.join().unwrap();
+        }
+
+        // try_iter stops at an empty queue, the sender is still there
+        let (wr, rd) = new_mpsc::<usize>(4, WaitType::SleepWait);
+        wr.send(0).unwrap();
+        wr.send(1).unwrap();
+        assert_eq!(rd.try_iter().collect::<Vec<_>>(), [0, 1]);
+        assert_eq!(rd.try_iter().next(), None);
+
+        // drain stops at the elements there when it began, and at an empty queue
+        for i in 2..4 {
+            wr.send(i).unwrap();
+        }
+        let mut v = Vec::new();
+        for e in rd.drain() {
+            let _ = wr.try_send(e + 10);
+            v.push(e);
+        }
+        assert_eq!(v, [2, 3]);
+        assert_eq!(rd.drain().collect::<Vec<_>>(), [12, 13]);
+        assert_eq!(rd.drain().next(), None);
+        // and after the senders are gone, with what they sent before
+        wr.send(20).unwrap();
+        drop(wr);
+        assert_eq!(rd.drain().collect::<Vec<_>>(), [20]);
+        assert_eq!(rd.drain().next(), None);
+        assert_eq!(rd.into_iter().next(), None);
+    }
+
+    #[test]
+    fn test_len() {
+        let (wr, rd) = new_mpsc::<i64>(2, WaitType::SleepWait);
+        let wr2 = wr.clone();
+        assert_eq!((wr.len(), wr.capacity(), rd.capacity()), (0, 2, 2));
+        assert!(rd.is_empty() && !rd.is_full());
+        wr.send(1).unwrap();
+        assert_eq!(rd.len(), 1);
+        assert!(!rd.is_empty() && !rd.is_full());
+        wr2.send(2).unwrap();
+        assert_eq!(wr.len(), 2);
+        assert!(wr.is_full() && !wr.is_empty());
+        assert_eq!(rd.recv(), Ok(1));
+        assert_eq!(rd.len(), 1);
+        assert!(!rd.is_full());
+        assert!(!rd.is_disconnected() && !wr.is_disconnected());
+        drop(wr);
+        // a sender is left
+        assert!(!rd.is_disconnected());
+        drop(wr2);
+        assert!(rd.is_disconnected());
+        assert_eq!(rd.len(), 1);
+        assert_eq!(rd.recv(), Ok(2));
+        assert!(rd.is_empty() && rd.is_disconnected());
+
+        // the receiver is gone
+        let (wr, rd) = new_mpsc::<i64>(2, WaitType::SleepWait);
+        drop(rd);
+        assert!(wr.is_disconnected());
+    }
+}
@@ -0,0 +1,527 @@
+//!
+//! rust zero-capacity mpmc queue
+//!
+//! `send` hands the element to a receiver directly: it is put in a one
+//! element slot and the sender waits until a receiver has taken it. A sender
+//! that gives up takes its element back, so it is never delivered after
+//! `send` failed.
+//!
+
+use std::sync::{Mutex, MutexGuard, Condvar, Arc};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Instant;
+use crate::{SenderI, ReceiverI, WaitType, SendError, RecvError,
+            TrySendError, TryRecvError, SendTimeoutError, RecvTimeoutError};
+use crate::iter::{Iter, TryIter, Drain, IntoIter};
+use crate::snoozer::Snoozer;
+
+pub struct Sender<T> {
+    inner : Arc<RendezvousQueue<T>>,
+}
+impl<T> SenderI<T> for Sender<T> {
+    fn send(&self, e: T) -> Result<(), SendError<T>> {
+        self.inner.push(e)
+    }
+    fn try_send(&self, e: T) -> Result<(), TrySendError<T>> {
+        self.inner.try_push(e)
+    }
+    fn send_deadline(&self, e: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        self.inner.push_until(e, Some(deadline))
+    }
+}
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.inner.senders.fetch_add(1, Ordering::SeqCst);
+        Sender { inner: self.inner.clone() }
+    }
+}
+impl<T> Sender<T> {
+    /// See `RendezvousQueue::len`.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+    /// See `RendezvousQueue::is_empty`.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+    /// See `RendezvousQueue::is_full`.
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+    /// See `RendezvousQueue::capacity`.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See `RendezvousQueue::is_disconnected`.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.is_disconnected()
+    }
+}
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.inner.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.disconnect();
+        }
+    }
+}
+pub struct Receiver<T> {
+    inner: Arc<RendezvousQueue<T>>,
+}
+impl<T> ReceiverI<T> for Receiver<T> {
+    fn recv(&self) -> Result<T, RecvError> {
+        self.inner.pop()
+    }
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.inner.try_pop()
+    }
+    fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.inner.pop_until(Some(deadline))
+    }
+}
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.inner.receivers.fetch_add(1, Ordering::SeqCst);
+        Receiver { inner: self.inner.clone() }
+    }
+}
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if self.inner.receivers.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.disconnect();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// See `RendezvousQueue::len`.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+    /// See `RendezvousQueue::is_empty`.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+    /// See `RendezvousQueue::is_full`.
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+    /// See `RendezvousQueue::capacity`.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See `RendezvousQueue::is_disconnected`.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.is_disconnected()
+    }
+    /// Iterate over the elements, waiting for each one, until the queue is
+    /// disconnected and drained.
+    pub fn iter(&self) -> Iter<'_, T, Self> {
+        Iter::new(self)
+    }
+    /// Iterate over the elements available without waiting.
+    pub fn try_iter(&self) -> TryIter<'_, T, Self> {
+        TryIter::new(self)
+    }
+    /// Iterate over at most the elements in the queue now.
+    pub fn drain(&self) -> Drain<'_, T, Self> {
+        Drain::new(self, self.inner.len())
+    }
+}
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T, Self>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::new(self)
+    }
+}
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T, Receiver<T>>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// See `RendezvousQueue::new`.
+pub fn new_rendezvous<T>(wait_mode : WaitType) -> (Sender<T>, Receiver<T>) {
+    let qs = Arc::new(RendezvousQueue::<T>::new(wait_mode));
+    qs.senders.store(1, Ordering::SeqCst);
+    qs.receivers.store(1, Ordering::SeqCst);
+    let qr = qs.clone();
+    (Sender { inner: qs }, Receiver { inner: qr })
+}
+
+/// State protected by the lock.
+struct Slot<T> {
+    elem: Option<T>,
+    /// number of elements put in the slot, the ticket of the last sender
+    sent: usize,
+    /// number of elements taken from the slot
+    taken: usize,
+    /// receivers waiting in `pop_until`, `try_push` only succeeds if any
+    waiting: usize,
+}
+
+pub struct RendezvousQueue<T> {
+    slot: Mutex<Slot<T>>,
+    wait_mode: WaitType,
+    /// senders wait for the slot to be free or for their element to be taken
+    sem_room: Condvar,
+    /// receivers wait for an element
+    sem_elem: Condvar,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    disconnected: AtomicBool,
+}
+
+impl<T> RendezvousQueue<T> {
+    /// Panics on `WaitType::Futex`: the waiters sleep on the slot lock.
+    pub fn new(wait_mode: WaitType) -> RendezvousQueue<T> {
+        #[cfg(target_os = "linux")]
+        assert!(wait_mode != WaitType::Futex, "RendezvousQueue has no Futex wait mode");
+        RendezvousQueue {
+            slot: Mutex::new(Slot { elem: None, sent: 0, taken: 0, waiting: 0 }),
+            wait_mode,
+            sem_room: Default::default(),
+            sem_elem: Default::default(),
+            senders: AtomicUsize::new(0),
+            receivers: AtomicUsize::new(0),
+            disconnected: AtomicBool::new(false),
+        }
+    }
+
+    /// Called when the last Sender or the last Receiver is dropped: wake up
+    /// all waiting senders and receivers.
+    fn disconnect(&self) {
+        self.disconnected.store(true, Ordering::SeqCst);
+        let _g = self.slot.lock().unwrap();
+        self.sem_room.notify_all();
+        self.sem_elem.notify_all();
+    }
+    /// The element offered by a waiting sender and not taken yet, 0 or 1.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.slot.lock().unwrap().elem.is_some() as usize
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Always true, a sender waits for a receiver.
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        true
+    }
+    /// Always 0.
+    pub fn capacity(&self) -> usize {
+        0
+    }
+    /// All Senders or all Receivers are gone. Stays true once true,
+    /// elements sent before can still be received.
+    #[inline]
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::SeqCst)
+    }
+    #[inline]
+    fn before(deadline: Option<Instant>) -> bool {
+        match deadline {
+            Some(d) => Instant::now() < d,
+            None => true,
+        }
+    }
+    /// Wait for the slot to change. BusyWait releases the lock and spins,
+    /// SleepWait waits on `cv` until notified or `deadline`, Adaptive snoozes
+    /// with `snoozer` until it is completed, then waits like SleepWait.
+    fn wait<'a>(&'a self, g: MutexGuard<'a, Slot<T>>, cv: &Condvar, deadline: Option<Instant>,
+                snoozer: &mut Snoozer) -> MutexGuard<'a, Slot<T>> {
+        match self.wait_mode {
+            WaitType::BusyWait => {
+                drop(g);
+                std::hint::spin_loop();
+                self.slot.lock().unwrap()
+            }
+            WaitType::Adaptive { .. } if !snoozer.completed() => {
+                drop(g);
+                snoozer.snooze();
+                self.slot.lock().unwrap()
+            }
+            _ => match deadline {
+                None => cv.wait(g).unwrap(),
+                Some(d) => {
+                    let now = Instant::now();
+                    if now >= d {
+                        return g;
+                    }
+                    cv.wait_timeout(g, d - now).unwrap().0
+                }
+            },
+        }
+    }
+    /// Put `e` in the free slot, returns the ticket of the sender.
+    fn put_locked(&self, g: &mut MutexGuard<'_, Slot<T>>, e: T) -> usize {
+        g.elem = Some(e);
+        g.sent += 1;
+        if self.wait_mode.parks() {
+            self.sem_elem.notify_one();
+        }
+        g.sent
+    }
+    /// Take the element from the slot, wake up its sender.
+    fn take_locked(&self, g: &mut MutexGuard<'_, Slot<T>>) -> Option<T> {
+        let e = g.elem.take()?;
+        g.taken = g.sent;
+        if self.wait_mode.parks() {
+            // the sender of `e` and the senders waiting for the slot
+            self.sem_room.notify_all();
+        }
+        Some(e)
+    }
+
+    pub fn push(&self, e: T) -> Result<(), SendError<T>> {
+        self.push_until(e, None).map_err(|e| match e {
+            SendTimeoutError::Disconnected(e) => SendError::Disconnected(e),
+            SendTimeoutError::Timeout(_) => unreachable!(),
+        })
+    }
+    /// Wait until a receiver has taken `e`, giving up at `deadline` if there is one.
+    fn push_until(&self, e: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        let mut snoozer = self.wait_mode.snoozer();
+        let mut g = self.slot.lock().unwrap();
+        while g.elem.is_some() {
+            if self.is_disconnected() {
+                return Err(SendTimeoutError::Disconnected(e));
+            }
+            if !Self::before(deadline) {
+                return Err(SendTimeoutError::Timeout(e));
+            }
+            g = self.wait(g, &self.sem_room, deadline, &mut snoozer);
+        }
+        if self.is_disconnected() {
+            return Err(SendTimeoutError::Disconnected(e));
+        }
+
+        let ticket = self.put_locked(&mut g, e);
+        while g.taken < ticket {
+            let disconnected = self.is_disconnected();
+            if disconnected || !Self::before(deadline) {
+                // not taken yet, so the element in the slot is still ours
+                let e = g.elem.take().unwrap();
+                if self.wait_mode.parks() {
+                    self.sem_room.notify_all();
+                }
+                return Err(if disconnected {
+                    SendTimeoutError::Disconnected(e)
+                } else {
+                    SendTimeoutError::Timeout(e)
+                });
+            }
+            g = self.wait(g, &self.sem_room, deadline, &mut snoozer);
+        }
+        Ok(())
+    }
+
+    pub fn pop(&self) -> Result<T, RecvError> {
+        self.pop_until(None).map_err(|_| RecvError::Disconnected)
+    }
+    /// Wait for a sender, giving up at `deadline` if there is one.
+    fn pop_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let mut snoozer = self.wait_mode.snoozer();
+        let mut g = self.slot.lock().unwrap();
+        g.waiting += 1;
+        let r = loop {
+            // an element put while we were waiting is ours even if the deadline passed
+            if let Some(e) = self.take_locked(&mut g) {
+                break Ok(e);
+            }
+            if self.is_disconnected() {
+                break Err(RecvTimeoutError::Disconnected);
+            }
+            if !Self::before(deadline) {
+                break Err(RecvTimeoutError::Timeout);
+            }
+            g = self.wait(g, &self.sem_elem, deadline, &mut snoozer);
+        };
+        g.waiting -= 1;
+        r
+    }
+
+    /// Succeeds only if a receiver is waiting to take `e`.
+    pub fn try_push(&self, e: T) -> Result<(), TrySendError<T>> {
+        let mut g = self.slot.lock().unwrap();
+        if self.is_disconnected() {
+            return Err(TrySendError::Disconnected(e));
+        }
+        if g.elem.is_some() || g.waiting == 0 {
+            return Err(TrySendError::Full(e));
+        }
+        self.put_locked(&mut g, e);
+        Ok(())
+    }
+    /// Succeeds only if a sender is waiting in `send`.
+    pub fn try_pop(&self) -> Result<T, TryRecvError> {
+        let mut g = self.slot.lock().unwrap();
+        match self.take_locked(&mut g) {
+            Some(e) => Ok(e),
+            None if self.is_disconnected() => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+unsafe impl<T: Send> Send for RendezvousQueue<T>{}
+unsafe impl<T: Send> Sync for RendezvousQueue<T>{}
+
+impl<T> SenderI<T> for RendezvousQueue<T> {
+    fn send(&self, e: T) -> Result<(), SendError<T>> {
+        self.push(e)
+    }
+    fn try_send(&self, e: T) -> Result<(), TrySendError<T>> {
+        self.try_push(e)
+    }
+    fn send_deadline(&self, e: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        self.push_until(e, Some(deadline))
+    }
+}
+impl<T> ReceiverI<T> for RendezvousQueue<T> {
+    fn recv(&self) -> Result<T, RecvError> {
+        self.pop()
+    }
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.try_pop()
+    }
+    fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.pop_until(Some(deadline))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::rendezvous::new_rendezvous;
+    use crate::{SenderI, ReceiverI, WaitType, SendError, RecvError,
+                TrySendError, TryRecvError, SendTimeoutError, RecvTimeoutError};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test1() {
+        for &mode in &[WaitType::SleepWait, WaitType::BusyWait, WaitType::adaptive()] {
+            let (wr, rd) = new_rendezvous::<usize>(mode);
+            let ts: Vec<_> = (0..2).map(|_| {
+                let wr = wr.clone();
+                thread::spawn(move || {
+                    for i in 0..200 {
+                        wr.send(i).unwrap();
+                    }
+                })
+            }).collect();
+            drop(wr);
+            let rd2 = rd.clone();
+            let t = thread::spawn(move || {
+                let mut sum = 0;
+                while let Ok(e) = rd2.recv() {
+                    sum += e;
+                }
+                sum
+            });
+            let mut sum = 0;
+            while let Ok(e) = rd.recv() {
+                sum += e;
+            }
+            for t in ts {
+                t.join().unwrap();
+            }
+            assert_eq!(sum + t.join().unwrap(), 2 * (0..200).sum::<usize>());
+        }
+    }
+
+    #[test]
+    fn test_handoff() {
+        // send returns only after the element is taken
+        let (wr, rd) = new_rendezvous::<i64>(WaitType::SleepWait);
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            rd.recv()
+        });
+        wr.send(1).unwrap();
+        assert_eq!(t.join().unwrap(), Ok(1));
+    }
+
+    #[test]
+    fn test_try() {
+        let (wr, rd) = new_rendezvous::<i64>(WaitType::SleepWait);
+        // nobody on the other side
+        assert_eq!(wr.try_send(1), Err(TrySendError::Full(1)));
+        assert_eq!(rd.try_recv(), Err(TryRecvError::Empty));
+
+        let t = thread::spawn(move || {
+            wr.send(2).unwrap();
+            wr
+        });
+        let e = loop {
+            match rd.try_recv() {
+                Err(TryRecvError::Empty) => thread::yield_now(),
+                r => break r,
+            }
+        };
+        assert_eq!(e, Ok(2));
+        let wr = t.join().unwrap();
+
+        let t = thread::spawn(move || rd.recv());
+        loop {
+            match wr.try_send(3) {
+                Err(TrySendError::Full(_)) => thread::yield_now(),
+                r => break r.unwrap(),
+            }
+        }
+        assert_eq!(t.join().unwrap(), Ok(3));
+        assert_eq!(wr.try_send(4), Err(TrySendError::Disconnected(4)));
+    }
+
+    #[test]
+    fn test_timeout() {
+        for &mode in &[WaitType::SleepWait, WaitType::BusyWait, WaitType::adaptive()] {
+            let (wr, rd) = new_rendezvous::<i64>(mode);
+            assert_eq!(wr.send_timeout(1, Duration::from_millis(20)), Err(SendTimeoutError::Timeout(1)));
+            assert_eq!(rd.recv_timeout(Duration::from_millis(20)), Err(RecvTimeoutError::Timeout));
+            // the timed out element was taken back
+            assert_eq!(rd.try_recv(), Err(TryRecvError::Empty));
+        }
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (wr, rd) = new_rendezvous::<i64>(WaitType::SleepWait);
+        let t = thread::spawn(move || wr.send(1));
+        thread::sleep(Duration::from_millis(50));
+        drop(rd);
+        assert_eq!(t.join().unwrap(), Err(SendError::Disconnected(1)));
+
+        let (wr, rd) = new_rendezvous::<i64>(WaitType::SleepWait);
+        let t = thread::spawn(move || rd.recv());
+        thread::sleep(Duration::from_millis(50));
+        drop(wr);
+        assert_eq!(t.join().unwrap(), Err(RecvError::Disconnected));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[should_panic(expected = "no Futex wait mode")]
+    fn test_futex() {
+        let _ = new_rendezvous::<i64>(WaitType::Futex);
+    }
+
+    #[test]
+    fn test_len() {
+        let (wr, rd) = new_rendezvous::<i64>(WaitType::SleepWait);
+        assert_eq!((rd.len(), rd.capacity()), (0, 0));
+        assert!(rd.is_empty() && wr.is_full());
+        let t = thread::spawn(move || wr.send(1));
+        // the sender offers its element until a receiver takes it
+        while rd.is_empty() {
+            thread::yield_now();
+        }
+        assert_eq!(rd.len(), 1);
+        assert_eq!(rd.recv(), Ok(1));
+        t.join().unwrap().unwrap();
+        assert!(rd.is_disconnected());
+    }
+}
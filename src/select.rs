@@ -0,0 +1,197 @@
+//!
+//! wait on several queue handles at once
+//!
+//! `Select` blocks the thread until one of its operations would not block:
+//! a receiver with an element, a sender with room, or a handle of a
+//! disconnected queue. The thread registers itself in the `WakerSet`s of the
+//! queues, like the async futures do, and parks.
+//!
+//! `ready` only tells which operation is ready, the caller then does it with
+//! `try_recv`/`try_send`. When several receivers share an mpmc queue another
+//! one may take the element first, so `Empty` means select again.
+//!
+
+use std::task::Waker;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::{SenderI, ReceiverI};
+use crate::waker::thread_waker;
+
+/// A queue handle `Select` can wait on.
+pub trait SelectHandle {
+    /// `recv` or `send` on the handle would not block.
+    fn is_ready(&self) -> bool;
+    /// Wake up `waker` once the handle may have become ready.
+    fn register(&self, waker: &Waker);
+}
+
+pub struct Select<'a> {
+    handles: Vec<&'a dyn SelectHandle>,
+    /// first handle to check, rotates so that no operation starves
+    start: usize,
+}
+
+impl<'a> Select<'a> {
+    pub fn new() -> Select<'a> {
+        Select { handles: Vec::new(), start: 0 }
+    }
+
+    /// Add a receive operation, returns its index.
+    pub fn recv<T, R: ReceiverI<T> + SelectHandle>(&mut self, r: &'a R) -> usize {
+        self.handles.push(r);
+        self.handles.len() - 1
+    }
+    /// Add a send operation, returns its index.
+    pub fn send<T, S: SenderI<T> + SelectHandle>(&mut self, s: &'a S) -> usize {
+        self.handles.push(s);
+        self.handles.len() - 1
+    }
+
+    /// Index of a ready operation, `None` if there is none. This is the
+    /// default arm.
+    pub fn try_ready(&mut self) -> Option<usize> {
+        let n = self.handles.len();
+        for k in 0..n {
+            let i = (self.start + k) % n;
+            if self.handles[i].is_ready() {
+                self.start = (i + 1) % n;
+                return Some(i);
+            }
+        }
+        None
+    }
+    /// Wait until an operation is ready, returns its index.
+    pub fn ready(&mut self) -> usize {
+        assert!(!self.handles.is_empty(), "no operation to select");
+        self.ready_until(None).unwrap()
+    }
+    /// Wait at most `timeout` for an operation, `None` if none got ready.
+    pub fn ready_timeout(&mut self, timeout: Duration) -> Option<usize> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.ready_deadline(deadline),
+            None => Some(self.ready()),
+        }
+    }
+    /// Wait until `deadline` for an operation, `None` if none got ready.
+    pub fn ready_deadline(&mut self, deadline: Instant) -> Option<usize> {
+        self.ready_until(Some(deadline))
+    }
+
+    fn ready_until(&mut self, deadline: Option<Instant>) -> Option<usize> {
+        if let Some(i) = self.try_ready() {
+            return Some(i);
+        }
+        let waker = thread_waker();
+        loop {
+            // the queues drop the wakers when they wake them, register again
+            // each round. Checked after registering, so no wake up is lost
+            for h in &self.handles {
+                h.register(&waker);
+            }
+            if let Some(i) = self.try_ready() {
+                return Some(i);
+            }
+            match deadline {
+                None => thread::park(),
+                Some(d) => {
+                    let now = Instant::now();
+                    if now >= d {
+                        return None;
+                    }
+                    thread::park_timeout(d - now);
+                }
+            }
+        }
+    }
+}
+impl Default for Select<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::select::Select;
+    use crate::spsc::new_spsc;
+    use crate::mpmc::new_mpmc;
+    use crate::{SenderI, ReceiverI, WaitType, TryRecvError};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test1() {
+        // data and control queues
+        let (data_wr, data_rd) = new_spsc::<usize>(4, WaitType::SleepWait);
+        let (ctl_wr, ctl_rd) = new_mpmc::<&str>(1);
+        let t = thread::spawn(move || {
+            for i in 0..100 {
+                data_wr.send(i).unwrap();
+            }
+            ctl_wr.send("stop").unwrap();
+            thread::sleep(Duration::from_millis(50));
+        });
+
+        let mut sel = Select::new();
+        let data = sel.recv(&data_rd);
+        let ctl = sel.recv(&ctl_rd);
+        let mut v = Vec::new();
+        loop {
+            let i = sel.ready();
+            if i == data {
+                v.push(data_rd.try_recv().unwrap());
+            } else if i == ctl {
+                assert_eq!(ctl_rd.try_recv(), Ok("stop"));
+                break;
+            }
+        }
+        // "stop" may come before the last elements
+        while let Ok(e) = data_rd.try_recv() {
+            v.push(e);
+        }
+        t.join().unwrap();
+        assert_eq!(v, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_send() {
+        let (wr1, rd1) = new_spsc::<i64>(1, WaitType::BusyWait);
+        let (wr2, rd2) = new_mpmc::<i64>(1);
+        wr1.send(1).unwrap();
+        wr2.send(2).unwrap();
+
+        let mut sel = Select::new();
+        let s1 = sel.send(&wr1);
+        let s2 = sel.send(&wr2);
+        assert_eq!(sel.try_ready(), None);
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            assert_eq!(rd2.recv(), Ok(2));
+            rd2
+        });
+        assert_eq!(sel.ready(), s2);
+        assert_ne!(s1, s2);
+        drop(t.join().unwrap());
+        drop(rd1);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (_wr1, rd1) = new_spsc::<i64>(1, WaitType::SleepWait);
+        let (wr2, rd2) = new_mpmc::<i64>(1);
+        let mut sel = Select::new();
+        sel.recv(&rd1);
+        let r2 = sel.recv(&rd2);
+        assert_eq!(sel.try_ready(), None);
+        assert_eq!(sel.ready_timeout(Duration::from_millis(20)), None);
+
+        // a disconnected queue is ready
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(wr2);
+        });
+        assert_eq!(sel.ready_timeout(Duration::from_secs(10)), Some(r2));
+        assert_eq!(rd2.try_recv(), Err(TryRecvError::Disconnected));
+        t.join().unwrap();
+    }
+}
@@ -0,0 +1,536 @@
+//!
+//! rust spsc queue in shared memory, between processes on one linux box
+//!
+//! `ShmSpsc::create` makes a named mapping in `/dev/shm`, which other processes
+//! map with `ShmSpsc::open`. `ShmSpsc::memfd` makes an anonymous one, which
+//! children inherit through `fork`. The mapping starts with a header telling
+//! its layout (magic, version, capacity, element size and alignment), checked
+//! by `open` against its `T`, then come the indices and the elements.
+//!
+//! A process takes one side with `sender` or `receiver`, each side is taken
+//! once. The elements are `Copy`, they must be plain data without pointers into
+//! the memory of a process. Dropping a side disconnects the queue. A process
+//! dying without dropping its side leaves the other one waiting.
+//!
+//! The wait mode is the one of the process: `BusyWait` spins, the other modes
+//! sleep on futexes in the mapping, `Adaptive` after spinning and yielding.
+//!
+
+use std::cell::Cell;
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Instant;
+use std::{io, mem, ptr};
+use crate::{SenderI, ReceiverI, WaitType, SendError, RecvError,
+            TrySendError, TryRecvError, SendTimeoutError, RecvTimeoutError};
+use crate::futex::FutexSem;
+use crate::iter::{Iter, TryIter, Drain, IntoIter};
+
+/// "QUEUESHM"
+const MAGIC: usize = 0x4d48_5345_5545_5551;
+/// changes with the layout of `Header`
+const VERSION: usize = 1;
+
+/// Start of the mapping.
+#[repr(C)]
+struct Header {
+    /// written last by `create`, an `open` seeing it sees the rest
+    magic: AtomicUsize,
+    version: usize,
+    capacity: usize,
+    elem_size: usize,
+    elem_align: usize,
+    sender_taken: AtomicBool,
+    receiver_taken: AtomicBool,
+    disconnected: AtomicBool,
+    _pad0: [i64; 7],
+    count: AtomicUsize,
+    _pad1: [i64; 7],
+    i_idx: Cell<usize>,
+    /// the receiver sleeps on it
+    futex_elem: FutexSem,
+    _pad2: [i64; 7],
+    o_idx: Cell<usize>,
+    /// the sender sleeps on it
+    futex_room: FutexSem,
+    _pad3: [i64; 7],
+}
+
+/// Offset of the elements in the mapping.
+fn buf_offset<T>() -> usize {
+    let align = mem::align_of::<T>().max(mem::align_of::<Header>());
+    mem::size_of::<Header>().div_ceil(align) * align
+}
+/// `None` if `cap` elements don't fit in the address space.
+fn map_size<T>(cap: usize) -> Option<usize> {
+    mem::size_of::<T>().checked_mul(cap)
+        .and_then(|n| n.checked_add(buf_offset::<T>()))
+        .filter(|&n| n <= isize::MAX as usize)
+}
+
+fn cvt(r: libc::c_int) -> io::Result<libc::c_int> {
+    if r == -1 { Err(io::Error::last_os_error()) } else { Ok(r) }
+}
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// A mapped queue, shared by `ShmSpsc` and the side taken in this process.
+pub struct ShmSpsc<T: Copy> {
+    inner: Arc<ShmQueue<T>>,
+}
+
+impl<T: Copy> ShmSpsc<T> {
+    /// Create the queue `/dev/shm/<name>`, `name` starting with a `/`. Fails if
+    /// it exists, it stays until `unlink`.
+    pub fn create(name: &str, cap: usize, wait_mode: WaitType) -> io::Result<ShmSpsc<T>> {
+        assert!(cap >= 1, "capacity too small");
+        let cname = CString::new(name)?;
+        let fd = cvt(unsafe {
+            libc::shm_open(cname.as_ptr(), libc::O_RDWR | libc::O_CREAT | libc::O_EXCL, 0o600)
+        })?;
+        let r = ShmQueue::init(fd, cap, wait_mode);
+        if r.is_err() {
+            unsafe { libc::shm_unlink(cname.as_ptr()) };
+        }
+        r.map(|q| ShmSpsc { inner: Arc::new(q) })
+    }
+
+    /// Open the queue `name` made by `create` in another process. Fails with
+    /// `InvalidData` if its header does not match `T`.
+    pub fn open(name: &str, wait_mode: WaitType) -> io::Result<ShmSpsc<T>> {
+        let cname = CString::new(name)?;
+        let fd = cvt(unsafe { libc::shm_open(cname.as_ptr(), libc::O_RDWR, 0) })?;
+        let q = unsafe {
+            let mut st = mem::zeroed::<libc::stat>();
+            let r = cvt(libc::fstat(fd, &mut st))
+                .and_then(|_| ShmQueue::map(fd, st.st_size as usize, wait_mode));
+            libc::close(fd);
+            r?
+        };
+        q.check()?;
+        Ok(ShmSpsc { inner: Arc::new(q) })
+    }
+
+    /// Create an anonymous queue, for the children forked after it.
+    pub fn memfd(cap: usize, wait_mode: WaitType) -> io::Result<ShmSpsc<T>> {
+        assert!(cap >= 1, "capacity too small");
+        let fd = cvt(unsafe { libc::memfd_create(b"queue-shm\0".as_ptr() as *const libc::c_char, libc::MFD_CLOEXEC) })?;
+        ShmQueue::init(fd, cap, wait_mode).map(|q| ShmSpsc { inner: Arc::new(q) })
+    }
+
+    /// Remove the name of a queue, the processes which mapped it keep it.
+    pub fn unlink(name: &str) -> io::Result<()> {
+        let cname = CString::new(name)?;
+        cvt(unsafe { libc::shm_unlink(cname.as_ptr()) }).map(|_| ())
+    }
+
+    /// Take the sending side, `None` if a process already took it.
+    pub fn sender(&self) -> Option<Sender<T>> {
+        if self.inner.header().sender_taken.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        Some(Sender { inner: self.inner.clone(), _not_sync: PhantomData })
+    }
+    /// Take the receiving side, `None` if a process already took it.
+    pub fn receiver(&self) -> Option<Receiver<T>> {
+        if self.inner.header().receiver_taken.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        Some(Receiver { inner: self.inner.clone(), _not_sync: PhantomData })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+}
+
+/// The sending side. It can be sent to another thread but not shared:
+///
+/// ```compile_fail
+/// fn shared<S: Sync>() {}
+/// shared::<queue::shm::Sender<u64>>();
+/// ```
+pub struct Sender<T: Copy> {
+    inner: Arc<ShmQueue<T>>,
+    /// `push` moves `i_idx` without a lock, so only one thread may send
+    _not_sync: PhantomData<Cell<()>>,
+}
+impl<T: Copy> SenderI<T> for Sender<T> {
+    fn send(&self, e: T) -> Result<(), SendError<T>> {
+        self.inner.push_until(e, None).map_err(|err| match err {
+            SendTimeoutError::Disconnected(e) => SendError::Disconnected(e),
+            SendTimeoutError::Timeout(_) => unreachable!(),
+        })
+    }
+    fn try_send(&self, e: T) -> Result<(), TrySendError<T>> {
+        self.inner.try_push(e)
+    }
+    fn send_deadline(&self, e: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        self.inner.push_until(e, Some(deadline))
+    }
+}
+impl<T: Copy> Sender<T> {
+    /// Number of elements in the queue, see the crate docs on how current it is.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// The other side is gone. Stays true once true, elements sent
+    /// before can still be received.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.is_disconnected()
+    }
+}
+impl<T: Copy> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.inner.disconnect();
+    }
+}
+
+/// The receiving side. It can be sent to another thread but not shared:
+///
+/// ```compile_fail
+/// fn shared<R: Sync>() {}
+/// shared::<queue::shm::Receiver<u64>>();
+/// ```
+pub struct Receiver<T: Copy> {
+    inner: Arc<ShmQueue<T>>,
+    /// `pop` moves `o_idx` without a lock, so only one thread may receive
+    _not_sync: PhantomData<Cell<()>>,
+}
+impl<T: Copy> ReceiverI<T> for Receiver<T> {
+    fn recv(&self) -> Result<T, RecvError> {
+        self.inner.pop_until(None).map_err(|_| RecvError::Disconnected)
+    }
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.inner.try_pop()
+    }
+    fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.inner.pop_until(Some(deadline))
+    }
+}
+impl<T: Copy> Receiver<T> {
+    /// Number of elements in the queue, see the crate docs on how current it is.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// The other side is gone. Stays true once true, elements sent
+    /// before can still be received.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.is_disconnected()
+    }
+    /// Iterator waiting for each element, see `iter::Iter`.
+    pub fn iter(&self) -> Iter<'_, T, Self> {
+        Iter::new(self)
+    }
+    /// Iterator over the elements available without waiting.
+    pub fn try_iter(&self) -> TryIter<'_, T, Self> {
+        TryIter::new(self)
+    }
+    /// Iterator over the elements in the queue now.
+    pub fn drain(&self) -> Drain<'_, T, Self> {
+        Drain::new(self, self.inner.len())
+    }
+}
+impl<T: Copy> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.disconnect();
+    }
+}
+impl<T: Copy> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T, Self>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::new(self)
+    }
+}
+impl<'a, T: Copy> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T, Receiver<T>>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+struct ShmQueue<T> {
+    base: *mut u8,
+    len: usize,
+    wait_mode: WaitType,
+    _elem: PhantomData<T>,
+}
+
+impl<T> ShmQueue<T> {
+    /// Size the new file `fd`, map it and write the header. Closes `fd`.
+    fn init(fd: libc::c_int, cap: usize, wait_mode: WaitType) -> io::Result<ShmQueue<T>> {
+        let r = match map_size::<T>(cap) {
+            Some(len) => cvt(unsafe { libc::ftruncate(fd, len as libc::off_t) })
+                .and_then(|_| Self::map(fd, len, wait_mode)),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "capacity too large")),
+        };
+        unsafe { libc::close(fd) };
+        let q = r?;
+        unsafe {
+            ptr::write(q.base as *mut Header, Header {
+                magic: AtomicUsize::new(0),
+                version: VERSION,
+                capacity: cap,
+                elem_size: mem::size_of::<T>(),
+                elem_align: mem::align_of::<T>(),
+                sender_taken: AtomicBool::new(false),
+                receiver_taken: AtomicBool::new(false),
+                disconnected: AtomicBool::new(false),
+                _pad0: [0; 7],
+                count: AtomicUsize::new(0),
+                _pad1: [0; 7],
+                i_idx: Cell::new(0),
+                futex_elem: FutexSem::new_shared(),
+                _pad2: [0; 7],
+                o_idx: Cell::new(0),
+                futex_room: FutexSem::new_shared(),
+                _pad3: [0; 7],
+            });
+        }
+        q.header().magic.store(MAGIC, Ordering::SeqCst);
+        Ok(q)
+    }
+
+    fn map(fd: libc::c_int, len: usize, wait_mode: WaitType) -> io::Result<ShmQueue<T>> {
+        if len < mem::size_of::<Header>() {
+            return Err(invalid("shared memory too small for a queue"));
+        }
+        let base = unsafe {
+            libc::mmap(ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE,
+                       libc::MAP_SHARED, fd, 0)
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ShmQueue { base: base as *mut u8, len, wait_mode, _elem: PhantomData })
+    }
+
+    /// The header matches this build and `T`.
+    fn check(&self) -> io::Result<()> {
+        let h = self.header();
+        if h.magic.load(Ordering::SeqCst) != MAGIC {
+            return Err(invalid("not a queue, or not created yet"));
+        }
+        if h.version != VERSION {
+            return Err(invalid("queue of another version"));
+        }
+        if h.elem_size != mem::size_of::<T>() || h.elem_align != mem::align_of::<T>() {
+            return Err(invalid("queue of another element type"));
+        }
+        if h.capacity == 0 || !matches!(map_size::<T>(h.capacity), Some(n) if n <= self.len) {
+            return Err(invalid("queue capacity does not match its size"));
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn header(&self) -> &Header {
+        unsafe { &*(self.base as *const Header) }
+    }
+    #[inline]
+    fn buf(&self) -> *mut T {
+        unsafe { self.base.add(buf_offset::<T>()) as *mut T }
+    }
+
+    #[inline]
+    fn next_idx(&self, idx: usize) -> usize {
+        if idx + 1 == self.header().capacity { 0 } else { idx + 1 }
+    }
+    fn put_elem(&self, e: T) {
+        let h = self.header();
+        unsafe { ptr::write(self.buf().add(h.i_idx.get()), e) };
+        h.i_idx.set(self.next_idx(h.i_idx.get()));
+    }
+    fn get_elem(&self) -> T {
+        let h = self.header();
+        let e = unsafe { ptr::read(self.buf().add(h.o_idx.get())) };
+        h.o_idx.set(self.next_idx(h.o_idx.get()));
+        e
+    }
+
+    fn disconnect(&self) {
+        let h = self.header();
+        h.disconnected.store(true, Ordering::SeqCst);
+        h.futex_room.wake_all();
+        h.futex_elem.wake_all();
+    }
+    #[inline]
+    fn is_disconnected(&self) -> bool {
+        self.header().disconnected.load(Ordering::SeqCst)
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        self.header().count.load(Ordering::SeqCst)
+    }
+    #[inline]
+    fn is_full(&self) -> bool {
+        self.len() == self.header().capacity
+    }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn capacity(&self) -> usize {
+        self.header().capacity
+    }
+
+    /// `None` or not yet expired.
+    #[inline]
+    fn before(deadline: Option<Instant>) -> bool {
+        match deadline {
+            Some(d) => Instant::now() < d,
+            None => true,
+        }
+    }
+
+    // the other side may wait in another mode, so it is always woken up.
+    // `FutexSem::wake` makes no syscall if nobody sleeps
+
+    fn push_until(&self, e: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        if self.is_disconnected() {
+            return Err(SendTimeoutError::Disconnected(e));
+        }
+        if self.is_full() {
+            self.wait_mode.snooze_until(|| !self.is_full() || self.is_disconnected());
+        }
+        while self.is_full() {
+            if self.is_disconnected() {
+                return Err(SendTimeoutError::Disconnected(e));
+            }
+            if !Self::before(deadline) {
+                return Err(SendTimeoutError::Timeout(e));
+            }
+            if self.wait_mode.parks() {
+                self.header().futex_room.wait(|| self.is_full() && !self.is_disconnected(), deadline);
+            }
+        }
+        self.put_elem(e);
+        if self.header().count.fetch_add(1, Ordering::SeqCst) == 0 {
+            self.header().futex_elem.wake();
+        }
+        Ok(())
+    }
+
+    fn pop_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        if self.is_empty() {
+            self.wait_mode.snooze_until(|| !self.is_empty() || self.is_disconnected());
+        }
+        while self.is_empty() {
+            // checked after `count`, elements sent before the disconnect
+            // are still received
+            if self.is_disconnected() && self.is_empty() {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            if !Self::before(deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            if self.wait_mode.parks() {
+                self.header().futex_elem.wait(|| self.is_empty() && !self.is_disconnected(), deadline);
+            }
+        }
+        let e = self.get_elem();
+        if self.header().count.fetch_sub(1, Ordering::SeqCst) == self.header().capacity {
+            self.header().futex_room.wake();
+        }
+        Ok(e)
+    }
+
+    fn try_push(&self, e: T) -> Result<(), TrySendError<T>> {
+        if self.is_disconnected() {
+            return Err(TrySendError::Disconnected(e));
+        }
+        if self.is_full() {
+            return Err(TrySendError::Full(e));
+        }
+        self.put_elem(e);
+        if self.header().count.fetch_add(1, Ordering::SeqCst) == 0 {
+            self.header().futex_elem.wake();
+        }
+        Ok(())
+    }
+
+    fn try_pop(&self) -> Result<T, TryRecvError> {
+        if self.is_empty() {
+            if self.is_disconnected() && self.is_empty() {
+                return Err(TryRecvError::Disconnected);
+            }
+            return Err(TryRecvError::Empty);
+        }
+        let e = self.get_elem();
+        if self.header().count.fetch_sub(1, Ordering::SeqCst) == self.header().capacity {
+            self.header().futex_room.wake();
+        }
+        Ok(e)
+    }
+}
+
+impl<T> Drop for ShmQueue<T> {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.base as *mut libc::c_void, self.len) };
+    }
+}
+
+unsafe impl<T: Send> Send for ShmQueue<T>{}
+unsafe impl<T: Send> Sync for ShmQueue<T>{}
+
+#[cfg(test)]
+mod tests{
+    use crate::shm::ShmSpsc;
+    use crate::{SenderI, WaitType, TrySendError};
+    use std::io;
+
+    #[test]
+    fn test_header() {
+        let name = format!("/queue-test-header-{}", std::process::id());
+        let q = ShmSpsc::<u64>::create(&name, 2, WaitType::SleepWait).unwrap();
+        assert_eq!(ShmSpsc::<u64>::create(&name, 2, WaitType::SleepWait).err().unwrap().kind(),
+                   io::ErrorKind::AlreadyExists);
+        let err = ShmSpsc::<u32>::open(&name, WaitType::SleepWait).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // each side is taken once, in any process
+        let q2 = ShmSpsc::<u64>::open(&name, WaitType::SleepWait).unwrap();
+        assert_eq!(q2.capacity(), 2);
+        let w = q.sender().unwrap();
+        assert!(q2.sender().is_none());
+        let r = q2.receiver().unwrap();
+        assert!(q.receiver().is_none());
+        ShmSpsc::<u64>::unlink(&name).unwrap();
+
+        w.send(1).unwrap();
+        w.send(2).unwrap();
+        assert_eq!(w.try_send(3), Err(TrySendError::Full(3)));
+        assert_eq!(r.try_iter().collect::<Vec<_>>(), vec![1, 2]);
+        drop(r);
+        assert_eq!(w.try_send(3), Err(TrySendError::Disconnected(3)));
+    }
+
+    #[test]
+    fn test_capacity_overflow() {
+        let err = ShmSpsc::<u64>::memfd(usize::MAX / 4, WaitType::SleepWait).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
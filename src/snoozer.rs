@@ -0,0 +1,51 @@
+//!
+//! backoff for waiting on a queue: spin, then yield
+//!
+
+use std::thread;
+
+/// Default limits, as in lfmpmc/go.
+pub(crate) const SPIN_LIMIT : u32 = 6;
+pub(crate) const YIELD_LIMIT : u32 = 10;
+/// A round spins at most `1 << MAX_SPIN_SHIFT` times.
+const MAX_SPIN_SHIFT : u32 = 16;
+
+/// Round `n` spins `1 << n` times while `n < spin_limit`, later rounds
+/// yield the thread. Completed after `yield_limit` rounds.
+pub(crate) struct Snoozer {
+    count: u32,
+    spin_limit: u32,
+    yield_limit: u32,
+}
+impl Snoozer {
+    pub(crate) fn new(spin_limit: u32, yield_limit: u32) -> Snoozer {
+        Snoozer { count: 0, spin_limit: spin_limit.min(MAX_SPIN_SHIFT), yield_limit }
+    }
+    /// Back off a failed CAS, never yields.
+    pub(crate) fn spin(&mut self) {
+        for _ in 0..1u32 << self.count.min(self.spin_limit) {
+            // notify cpu I'm spin
+            std::hint::spin_loop();
+        }
+        if self.count <= self.spin_limit {
+            self.count += 1;
+        }
+    }
+    /// Back off waiting for another thread.
+    pub(crate) fn snooze(&mut self) {
+        if self.count < self.spin_limit {
+            for _ in 0..1u32 << self.count {
+                std::hint::spin_loop();
+            }
+        } else {
+            thread::yield_now();
+        }
+        if self.count <= self.yield_limit {
+            self.count += 1;
+        }
+    }
+    /// Time to park.
+    pub(crate) fn completed(&self) -> bool {
+        self.count > self.yield_limit
+    }
+}
@@ -0,0 +1,1337 @@
+//!
+//! rust spsc queue
+//!
+
+use std::cell::Cell;
+use std::sync::{Mutex, Condvar, Arc};
+use std::ptr;
+use std::mem::MaybeUninit;
+use std::ops::{Deref, DerefMut};
+use std::alloc::Layout;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::task::Waker;
+use std::time::Instant;
+use crate::{SenderI, ReceiverI, WaitType, SendError, RecvError,
+            TrySendError, TryRecvError, SendTimeoutError, RecvTimeoutError};
+use crate::iter::{Iter, TryIter, Drain, IntoIter};
+#[cfg(target_os = "linux")]
+use crate::futex::FutexSem;
+use crate::waker::WakerSet;
+use crate::select::SelectHandle;
+#[cfg(feature = "stats")]
+use crate::stats::{Stats, StatCounters};
+#[cfg(feature = "async")]
+use crate::future::{SendFuture, RecvFuture};
+
+/// The sending half. It can be sent to another thread but not shared, as
+/// there is only one sender:
+///
+/// ```compile_fail
+/// fn shared<S: Sync>() {}
+/// shared::<queue::spsc::Sender<i64>>();
+/// ```
+pub struct Sender<T> {
+    inner : Arc<SpscQueue<T>>,
+}
+impl<T> SenderI<T> for Sender<T> {
+    fn send(&self, e: T) -> Result<(), SendError<T>> {
+        self.inner.push(e)
+    }
+    fn try_send(&self, e: T) -> Result<(), TrySendError<T>> {
+        self.inner.try_push(e)
+    }
+    fn send_deadline(&self, e: T, deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        self.inner.push_until(e, Some(deadline))
+    }
+}
+impl<T> Sender<T> {
+    /// See `SpscQueue::len`.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+    /// See `SpscQueue::is_empty`.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+    /// See `SpscQueue::is_full`.
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+    /// See `SpscQueue::capacity`.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See `SpscQueue::is_disconnected`.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.is_disconnected()
+    }
+    /// Snapshot of the queue's counters, see `SpscQueue::stats`.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+    /// See `SpscQueue::send_batch`.
+    pub fn send_batch<I: IntoIterator<Item = T>>(&self, iter: I) -> Result<(), SendError<Vec<T>>> {
+        self.inner.send_batch(iter)
+    }
+    /// Send without blocking the thread, the task waits for room.
+    #[cfg(feature = "async")]
+    pub fn send_async(&self, e: T) -> SendFuture<'_, T, Self> {
+        SendFuture::new(self, &self.inner.room_wakers, e)
+    }
+    /// Wait for room like `send`, then lend the next slot to build the element
+    /// in place. It is sent by `WriteGuard::commit`, dropping the guard
+    /// without commit leaves the queue as it was. An element built in the slot
+    /// is then not dropped, the guard can't tell whether there is one: it is
+    /// overwritten by the next send and whatever it owns leaks.
+    pub fn reserve(&mut self) -> Result<WriteGuard<'_, T>, SendError<()>> {
+        let slot = self.inner.reserve_slot()?;
+        Ok(WriteGuard { queue: &self.inner, slot: unsafe { &mut *slot } })
+    }
+}
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.inner.disconnect();
+    }
+}
+/// The receiving half. It can be sent to another thread but not shared, as
+/// there is only one receiver:
+///
+/// ```compile_fail
+/// fn shared<R: Sync>() {}
+/// shared::<queue::spsc::Receiver<i64>>();
+/// ```
+pub struct Receiver<T> {
+    inner: Arc<SpscQueue<T>>,
+}
+impl<T> ReceiverI<T> for Receiver<T> {
+    fn recv(&self) -> Result<T, RecvError> {
+        self.inner.pop()
+    }
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.inner.try_pop()
+    }
+    fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.inner.pop_until(Some(deadline))
+    }
+}
+impl<T> Receiver<T> {
+    /// See `SpscQueue::len`.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+    /// See `SpscQueue::is_empty`.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+    /// See `SpscQueue::is_full`.
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+    /// See `SpscQueue::capacity`.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See `SpscQueue::is_disconnected`.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.is_disconnected()
+    }
+    /// Snapshot of the queue's counters, see `SpscQueue::stats`.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+    /// Wait for an element like `recv`, then lend it in its slot. The slot
+    /// is freed when the guard is dropped.
+    pub fn recv_ref(&mut self) -> Result<ReadGuard<'_, T>, RecvError> {
+        if self.inner.wait_elem() == 0 {
+            return Err(RecvError::Disconnected);
+        }
+        Ok(ReadGuard { queue: &self.inner, elem: self.inner.front() })
+    }
+    /// The next element, left in the queue. `None` if the queue is empty.
+    pub fn peek(&mut self) -> Option<&T> {
+        if self.inner.count.load(Ordering::SeqCst) == 0 {
+            return None;
+        }
+        Some(unsafe { &*self.inner.front() })
+    }
+    /// Iterate over the elements, waiting for each one, until the queue is
+    /// disconnected and drained.
+    pub fn iter(&self) -> Iter<'_, T, Self> {
+        Iter::new(self)
+    }
+    /// Iterate over the elements available without waiting.
+    pub fn try_iter(&self) -> TryIter<'_, T, Self> {
+        TryIter::new(self)
+    }
+    /// Iterate over at most the elements in the queue now.
+    pub fn drain(&self) -> Drain<'_, T, Self> {
+        Drain::new(self, self.inner.len())
+    }
+    /// See `SpscQueue::recv_batch`.
+    pub fn recv_batch(&self, buf: &mut Vec<T>, max: usize) -> Result<usize, RecvError> {
+        self.inner.recv_batch(buf, max)
+    }
+    /// Receive without blocking the thread, the task waits for an element.
+    #[cfg(feature = "async")]
+    pub fn recv_async(&self) -> RecvFuture<'_, T, Self> {
+        RecvFuture::new(self, &self.inner.elem_wakers)
+    }
+}
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.disconnect();
+    }
+}
+// the queue is not Sync, but its only sender and only receiver may be on
+// two threads: one pushes and the other pops
+unsafe impl<T: Send> Send for Sender<T>{}
+unsafe impl<T: Send> Send for Receiver<T>{}
+
+impl<T> SelectHandle for Sender<T> {
+    fn is_ready(&self) -> bool {
+        self.inner.count.load(Ordering::SeqCst) < self.inner.capacity || self.inner.is_disconnected()
+    }
+    fn register(&self, waker: &Waker) {
+        self.inner.room_wakers.register(waker);
+    }
+}
+impl<T> SelectHandle for Receiver<T> {
+    fn is_ready(&self) -> bool {
+        self.inner.count.load(Ordering::SeqCst) > 0 || self.inner.is_disconnected()
+    }
+    fn register(&self, waker: &Waker) {
+        self.inner.elem_wakers.register(waker);
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T, Self>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::new(self)
+    }
+}
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T, Receiver<T>>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// A reserved slot of the queue, see `Sender::reserve`. Dropping it without
+/// `write` or `commit` leaks an element built in the slot.
+pub struct WriteGuard<'a, T> {
+    queue: &'a SpscQueue<T>,
+    slot: &'a mut MaybeUninit<T>,
+}
+impl<T> WriteGuard<'_, T> {
+    /// Put `e` in the slot and send it.
+    pub fn write(self, e: T) {
+        self.slot.write(e);
+        unsafe { self.commit() }
+    }
+    /// Send the element built in the slot.
+    ///
+    /// # Safety
+    ///
+    /// The slot must be initialized.
+    pub unsafe fn commit(self) {
+        self.queue.commit_slot();
+    }
+}
+impl<T> Deref for WriteGuard<'_, T> {
+    type Target = MaybeUninit<T>;
+    fn deref(&self) -> &MaybeUninit<T> {
+        self.slot
+    }
+}
+impl<T> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut MaybeUninit<T> {
+        self.slot
+    }
+}
+
+/// An element borrowed in its slot, see `Receiver::recv_ref`.
+pub struct ReadGuard<'a, T> {
+    queue: &'a SpscQueue<T>,
+    elem: *const T,
+}
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.elem }
+    }
+}
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.queue.release_front();
+    }
+}
+
+pub fn new_spsc<T>(cap : usize, wait_mode : WaitType) -> (Sender<T>, Receiver<T>) {
+    let qs = Arc::new(SpscQueue::<T>::new(cap, wait_mode));
+    let qr = qs.clone();
+    (Sender { inner: qs }, Receiver { inner: qr })
+}
+
+/// The queue itself. It is not Sync: `push` and `pop` may run on two threads
+/// at once, but two `push`es or two `pop`s may not, which it can't check.
+/// `new_spsc` shares it between a `Sender` and a `Receiver` instead.
+pub struct SpscQueue<T> {
+    count: AtomicUsize,
+    _pad1: [i64; 7],
+    i_idx: Cell<usize>,
+    /// the receiver sleeps on it in Futex mode
+    #[cfg(target_os = "linux")]
+    futex_elem: FutexSem,
+    _pad2: [i64; 7],
+    o_idx: Cell<usize>,
+    /// the sender sleeps on it in Futex mode
+    #[cfg(target_os = "linux")]
+    futex_room: FutexSem,
+    _pad3: [i64; 7],
+    capacity: usize,
+    wait_mode: WaitType,
+    buf: *const T,
+    sem_room: (Mutex<()>, Condvar),
+    sem_elem: (Mutex<()>, Condvar),
+    room_wakers: WakerSet,
+    elem_wakers: WakerSet,
+    disconnected: AtomicBool,
+    #[cfg(feature = "stats")]
+    stats: StatCounters,
+}
+
+impl<T> SpscQueue<T> {
+    pub fn new(cap: usize, wait_mode: WaitType) -> SpscQueue<T> {
+        assert!(cap >= 1, "capacity too small");
+
+        unsafe {
+            let layout = Layout::array::<T>(cap).expect("capacity too large");
+            let buf_size = layout.size();
+
+            // a ZST needs no storage, `count` alone tracks occupancy
+            let buf = if buf_size == 0 {
+                ptr::NonNull::<T>::dangling().as_ptr() as *mut u8
+            } else {
+                std::alloc::alloc(layout)
+            };
+            if buf.is_null() {
+                panic!("Out of memory")
+            }
+
+            SpscQueue {
+                count: AtomicUsize::new(0),
+                _pad1: [0; 7],
+                i_idx: Cell::new(0),
+                #[cfg(target_os = "linux")]
+                futex_elem: FutexSem::new(),
+                _pad2: [0; 7],
+                o_idx: Cell::new(0),
+                #[cfg(target_os = "linux")]
+                futex_room: FutexSem::new(),
+                _pad3: [0; 7],
+                capacity: cap,
+                buf: buf as *const T,
+                wait_mode,
+                sem_room: (Mutex::new(()), Default::default()),
+                sem_elem: (Mutex::new(()), Default::default()),
+                room_wakers: WakerSet::new(),
+                elem_wakers: WakerSet::new(),
+                disconnected: AtomicBool::new(false),
+                #[cfg(feature = "stats")]
+                stats: StatCounters::new(),
+            }
+        }
+    }
+
+    /// Wrap around at `capacity`, which needs not be a power of two.
+    #[inline]
+    fn next_idx(&self, idx: usize) -> usize {
+        if idx + 1 == self.capacity { 0 } else { idx + 1 }
+    }
+    fn put_elem(&self, e : T) {
+        unsafe {
+            ptr::write::<T>(self.buf.add(self.i_idx.get()) as *mut T, e);
+        }
+        self.i_idx.set(self.next_idx(self.i_idx.get()));
+    }
+    fn get_elem(&self) -> T {
+        let e = unsafe { ptr::read::<T>(self.buf.add(self.o_idx.get())) };
+        self.o_idx.set(self.next_idx(self.o_idx.get()));
+        e
+    }
+    /// The element at `o_idx`, the queue must not be empty.
+    #[inline]
+    fn front(&self) -> *const T {
+        unsafe { self.buf.add(self.o_idx.get()) }
+    }
+    /// Drop the element at `o_idx` in its slot and free the slot.
+    fn release_front(&self) {
+        // dropped before the slot is given back to the sender
+        unsafe { ptr::drop_in_place(self.front() as *mut T) };
+        self.o_idx.set(self.next_idx(self.o_idx.get()));
+        let c = self.count.fetch_sub(1, Ordering::SeqCst);
+        #[cfg(feature = "stats")]
+        self.stats.received(1);
+        self.wake_sender(c);
+    }
+
+    /// Called when the Sender or the Receiver is dropped: wake up the other side,
+    /// which then fails once it would have to wait.
+    fn disconnect(&self) {
+        self.disconnected.store(true, Ordering::SeqCst);
+        self.room_wakers.wake_all();
+        self.elem_wakers.wake_all();
+        #[cfg(target_os = "linux")]
+        if let WaitType::Futex = self.wait_mode {
+            self.futex_room.wake_all();
+            self.futex_elem.wake_all();
+            return;
+        }
+        {
+            let _g = self.sem_room.0.lock().unwrap();
+            self.sem_room.1.notify_all();
+        }
+        let _g = self.sem_elem.0.lock().unwrap();
+        self.sem_elem.1.notify_all();
+    }
+    /// Snapshot of the counters, see `stats::Stats`.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+    /// Number of elements in the queue, see the crate docs on how current it is.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity
+    }
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    /// The Sender or the Receiver is gone. Stays true once true,
+    /// elements sent before can still be received.
+    #[inline]
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::SeqCst)
+    }
+    /// The sender is gone. Checked after `count`, so elements sent before
+    /// the disconnect are still visible here.
+    #[inline]
+    fn is_drained(&self) -> bool {
+        self.is_disconnected() && self.count.load(Ordering::SeqCst) == 0
+    }
+
+    /// `None` or not yet expired.
+    #[inline]
+    fn before(deadline: Option<Instant>) -> bool {
+        match deadline {
+            Some(d) => Instant::now() < d,
+            None => true,
+        }
+    }
+
+    fn push_busy(&self, e: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        while self.count.load(Ordering::SeqCst) == self.capacity {
+            #[cfg(feature = "stats")]
+            self.stats.spin();
+            if self.is_disconnected() {
+                return Err(SendTimeoutError::Disconnected(e));
+            }
+            if !Self::before(deadline) {
+                return Err(SendTimeoutError::Timeout(e));
+            }
+        }
+        self.put_elem(e);
+        let c = self.count.fetch_add(1, Ordering::SeqCst);
+        #[cfg(feature = "stats")]
+        self.stats.sent(1, c + 1);
+        self.wake_receiver(c);
+        Ok(())
+    }
+    fn pop_busy(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        while self.count.load(Ordering::SeqCst) == 0 {
+            #[cfg(feature = "stats")]
+            self.stats.spin();
+            if self.is_drained() {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            if !Self::before(deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+        }
+        let e = self.get_elem();
+        let c = self.count.fetch_sub(1, Ordering::SeqCst);
+        #[cfg(feature = "stats")]
+        self.stats.received(1);
+        self.wake_sender(c);
+        Ok(e)
+    }
+
+    fn push_sleep(&self, e: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        if self.count.load(Ordering::SeqCst) == self.capacity {
+            let mut g = self.sem_room.0.lock().unwrap();
+            while self.count.load(Ordering::SeqCst) == self.capacity {
+                if self.is_disconnected() {
+                    return Err(SendTimeoutError::Disconnected(e));
+                }
+                #[cfg(feature = "stats")]
+                let _w = self.stats.send_wait();
+                g = match deadline {
+                    None => self.sem_room.1.wait(g).unwrap(),
+                    Some(d) => {
+                        let now = Instant::now();
+                        if now >= d {
+                            return Err(SendTimeoutError::Timeout(e));
+                        }
+                        self.sem_room.1.wait_timeout(g, d - now).unwrap().0
+                    }
+                };
+            }
+        }
+        self.put_elem(e);
+        let c = self.count.fetch_add(1, Ordering::SeqCst);
+        #[cfg(feature = "stats")]
+        self.stats.sent(1, c + 1);
+        self.wake_receiver(c);
+        Ok(())
+    }
+    fn pop_sleep(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        if self.count.load(Ordering::SeqCst) == 0 {
+            let mut g = self.sem_elem.0.lock().unwrap();
+            while self.count.load(Ordering::SeqCst) == 0 {
+                if self.is_drained() {
+                    return Err(RecvTimeoutError::Disconnected);
+                }
+                #[cfg(feature = "stats")]
+                let _w = self.stats.recv_wait();
+                g = match deadline {
+                    None => self.sem_elem.1.wait(g).unwrap(),
+                    Some(d) => {
+                        let now = Instant::now();
+                        if now >= d {
+                            return Err(RecvTimeoutError::Timeout);
+                        }
+                        self.sem_elem.1.wait_timeout(g, d - now).unwrap().0
+                    }
+                };
+            }
+        }
+        let e = self.get_elem();
+        let c = self.count.fetch_sub(1, Ordering::SeqCst);
+        #[cfg(feature = "stats")]
+        self.stats.received(1);
+        self.wake_sender(c);
+        Ok(e)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn push_futex(&self, e: T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        let full = || self.count.load(Ordering::SeqCst) == self.capacity;
+        while full() {
+            if self.is_disconnected() {
+                return Err(SendTimeoutError::Disconnected(e));
+            }
+            if !Self::before(deadline) {
+                return Err(SendTimeoutError::Timeout(e));
+            }
+            #[cfg(feature = "stats")]
+            let _w = self.stats.send_wait();
+            self.futex_room.wait(|| full() && !self.is_disconnected(), deadline);
+        }
+        self.put_elem(e);
+        let c = self.count.fetch_add(1, Ordering::SeqCst);
+        #[cfg(feature = "stats")]
+        self.stats.sent(1, c + 1);
+        self.wake_receiver(c);
+        Ok(())
+    }
+    #[cfg(target_os = "linux")]
+    fn pop_futex(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let empty = || self.count.load(Ordering::SeqCst) == 0;
+        while empty() {
+            if self.is_drained() {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            if !Self::before(deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            #[cfg(feature = "stats")]
+            let _w = self.stats.recv_wait();
+            self.futex_elem.wait(|| empty() && !self.is_disconnected(), deadline);
+        }
+        let e = self.get_elem();
+        let c = self.count.fetch_sub(1, Ordering::SeqCst);
+        #[cfg(feature = "stats")]
+        self.stats.received(1);
+        self.wake_sender(c);
+        Ok(e)
+    }
+
+    /// `c` is the count before the push. Wakes up the receiver waiting
+    /// according to `wait_mode`, async receivers and `Select`.
+    #[inline]
+    fn wake_receiver(&self, c: usize) {
+        if c == 0 {
+            self.elem_wakers.wake_all();
+            match self.wait_mode {
+                WaitType::BusyWait => {}
+                #[cfg(target_os = "linux")]
+                WaitType::Futex => self.futex_elem.wake(),
+                _ => {
+                    let _g = self.sem_elem.0.lock().unwrap();
+                    self.sem_elem.1.notify_one();
+                }
+            }
+        }
+    }
+    /// `c` is the count before the pop. Wakes up the sender waiting
+    /// according to `wait_mode`, async senders and `Select`.
+    #[inline]
+    fn wake_sender(&self, c: usize) {
+        if c == self.capacity {
+            self.room_wakers.wake_all();
+            match self.wait_mode {
+                WaitType::BusyWait => {}
+                #[cfg(target_os = "linux")]
+                WaitType::Futex => self.futex_room.wake(),
+                _ => {
+                    let _g = self.sem_room.0.lock().unwrap();
+                    self.sem_room.1.notify_one();
+                }
+            }
+        }
+    }
+
+    #[inline]
+    pub fn push(&self, e : T) -> Result<(), SendError<T>> {
+        self.push_until(e, None).map_err(|err| match err {
+            SendTimeoutError::Disconnected(e) => SendError::Disconnected(e),
+            SendTimeoutError::Timeout(_) => unreachable!(),
+        })
+    }
+
+    #[inline]
+    pub fn pop(&self) -> Result<T, RecvError> {
+        self.pop_until(None).map_err(|_| RecvError::Disconnected)
+    }
+
+    /// Push, giving up at `deadline` if there is one.
+    #[inline]
+    fn push_until(&self, e : T, deadline: Option<Instant>) -> Result<(), SendTimeoutError<T>> {
+        if self.is_disconnected() {
+            return Err(SendTimeoutError::Disconnected(e));
+        }
+        match  self.wait_mode {
+            WaitType::BusyWait => self.push_busy(e, deadline),
+            #[cfg(target_os = "linux")]
+            WaitType::Futex => self.push_futex(e, deadline),
+            _ => {
+                self.wait_mode.snooze_until(|| {
+                    #[cfg(feature = "stats")]
+                    self.stats.spin();
+                    self.count.load(Ordering::SeqCst) < self.capacity || self.is_disconnected()
+                });
+                self.push_sleep(e, deadline)
+            }
+        }
+    }
+
+    /// Pop, giving up at `deadline` if there is one.
+    #[inline]
+    fn pop_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        match  self.wait_mode {
+            WaitType::BusyWait => self.pop_busy(deadline),
+            #[cfg(target_os = "linux")]
+            WaitType::Futex => self.pop_futex(deadline),
+            _ => {
+                self.wait_mode.snooze_until(|| {
+                    #[cfg(feature = "stats")]
+                    self.stats.spin();
+                    self.count.load(Ordering::SeqCst) > 0 || self.is_disconnected()
+                });
+                self.pop_sleep(deadline)
+            }
+        }
+    }
+
+    pub fn try_push(&self, e : T) -> Result<(), TrySendError<T>> {
+        if self.is_disconnected() {
+            return Err(TrySendError::Disconnected(e));
+        }
+        if self.count.load(Ordering::SeqCst) == self.capacity {
+            return Err(TrySendError::Full(e));
+        }
+        self.put_elem(e);
+        let c = self.count.fetch_add(1, Ordering::SeqCst);
+        #[cfg(feature = "stats")]
+        self.stats.sent(1, c + 1);
+        self.wake_receiver(c);
+        Ok(())
+    }
+
+    pub fn try_pop(&self) -> Result<T, TryRecvError> {
+        if self.count.load(Ordering::SeqCst) == 0 {
+            if self.is_drained() {
+                return Err(TryRecvError::Disconnected);
+            }
+            return Err(TryRecvError::Empty);
+        }
+        let e = self.get_elem();
+        let c = self.count.fetch_sub(1, Ordering::SeqCst);
+        #[cfg(feature = "stats")]
+        self.stats.received(1);
+        self.wake_sender(c);
+        Ok(e)
+    }
+
+    /// Wait for room like `push`, the slot at `i_idx` is then the sender's
+    /// until `commit_slot`.
+    fn reserve_slot(&self) -> Result<*mut MaybeUninit<T>, SendError<()>> {
+        if self.wait_room() == 0 {
+            return Err(SendError::Disconnected(()));
+        }
+        Ok(unsafe { self.buf.add(self.i_idx.get()) as *mut MaybeUninit<T> })
+    }
+    /// Publish the element written in the reserved slot.
+    fn commit_slot(&self) {
+        self.i_idx.set(self.next_idx(self.i_idx.get()));
+        let c = self.count.fetch_add(1, Ordering::SeqCst);
+        #[cfg(feature = "stats")]
+        self.stats.sent(1, c + 1);
+        self.wake_receiver(c);
+    }
+
+    /// Wait until there is room, returns the number of free slots or 0 if
+    /// the receiver is gone.
+    fn wait_room(&self) -> usize {
+        let room = || self.capacity - self.count.load(Ordering::SeqCst);
+        if self.is_disconnected() {
+            return 0;
+        }
+        if room() == 0 {
+            match self.wait_mode {
+                WaitType::BusyWait => while room() == 0 {
+                    #[cfg(feature = "stats")]
+                    self.stats.spin();
+                    if self.is_disconnected() {
+                        return 0;
+                    }
+                },
+                #[cfg(target_os = "linux")]
+                WaitType::Futex => while room() == 0 {
+                    if self.is_disconnected() {
+                        return 0;
+                    }
+                    #[cfg(feature = "stats")]
+                    let _w = self.stats.send_wait();
+                    self.futex_room.wait(|| room() == 0 && !self.is_disconnected(), None);
+                },
+                _ => {
+                    self.wait_mode.snooze_until(|| {
+                        #[cfg(feature = "stats")]
+                        self.stats.spin();
+                        room() > 0 || self.is_disconnected()
+                    });
+                    let mut g = self.sem_room.0.lock().unwrap();
+                    while room() == 0 {
+                        if self.is_disconnected() {
+                            return 0;
+                        }
+                        #[cfg(feature = "stats")]
+                        let _w = self.stats.send_wait();
+                        g = self.sem_room.1.wait(g).unwrap();
+                    }
+                }
+            }
+        }
+        room()
+    }
+    /// Wait until there are elements, returns their number or 0 if the
+    /// queue is drained.
+    fn wait_elem(&self) -> usize {
+        let len = || self.count.load(Ordering::SeqCst);
+        if len() == 0 {
+            match self.wait_mode {
+                WaitType::BusyWait => while len() == 0 {
+                    #[cfg(feature = "stats")]
+                    self.stats.spin();
+                    if self.is_drained() {
+                        return 0;
+                    }
+                },
+                #[cfg(target_os = "linux")]
+                WaitType::Futex => while len() == 0 {
+                    if self.is_drained() {
+                        return 0;
+                    }
+                    #[cfg(feature = "stats")]
+                    let _w = self.stats.recv_wait();
+                    self.futex_elem.wait(|| len() == 0 && !self.is_disconnected(), None);
+                },
+                _ => {
+                    self.wait_mode.snooze_until(|| {
+                        #[cfg(feature = "stats")]
+                        self.stats.spin();
+                        len() > 0 || self.is_disconnected()
+                    });
+                    let mut g = self.sem_elem.0.lock().unwrap();
+                    while len() == 0 {
+                        if self.is_drained() {
+                            return 0;
+                        }
+                        #[cfg(feature = "stats")]
+                        let _w = self.stats.recv_wait();
+                        g = self.sem_elem.1.wait(g).unwrap();
+                    }
+                }
+            }
+        }
+        len()
+    }
+
+    /// Push all elements of `iter`, waiting for room like `push`. Each run of
+    /// free slots is published with one `count` update and one wakeup.
+    /// If the receiver is gone, the elements not sent are returned.
+    pub fn send_batch<I: IntoIterator<Item = T>>(&self, iter: I) -> Result<(), SendError<Vec<T>>> {
+        let mut iter = iter.into_iter().peekable();
+        while iter.peek().is_some() {
+            let room = self.wait_room();
+            if room == 0 {
+                return Err(SendError::Disconnected(iter.collect()));
+            }
+            let mut n = 0;
+            for e in iter.by_ref().take(room) {
+                self.put_elem(e);
+                n += 1;
+            }
+            let c = self.count.fetch_add(n, Ordering::SeqCst);
+            #[cfg(feature = "stats")]
+            self.stats.sent(n, c + n);
+            self.wake_receiver(c);
+        }
+        Ok(())
+    }
+
+    /// Wait for elements like `pop`, then move up to `max` of them to `buf`
+    /// with one `count` update and one wakeup. Returns the number received.
+    pub fn recv_batch(&self, buf: &mut Vec<T>, max: usize) -> Result<usize, RecvError> {
+        if max == 0 {
+            return Ok(0);
+        }
+        let n = self.wait_elem().min(max);
+        if n == 0 {
+            return Err(RecvError::Disconnected);
+        }
+        buf.reserve(n);
+        for _ in 0..n {
+            buf.push(self.get_elem());
+        }
+        let c = self.count.fetch_sub(n, Ordering::SeqCst);
+        #[cfg(feature = "stats")]
+        self.stats.received(n);
+        self.wake_sender(c);
+        Ok(n)
+    }
+}
+impl<T> Drop for SpscQueue<T> {
+    fn drop(&mut self) {
+        // remove all elements
+        while self.count.load(Ordering::SeqCst) > 0 {
+            let _ = self.pop();
+        }
+
+        // free buffer
+        unsafe {
+            let layout = Layout::array::<T>(self.capacity).unwrap();
+            if layout.size() != 0 {
+                std::alloc::dealloc(self.buf as *mut u8, layout);
+            }
+        }
+    }
+}
+unsafe impl<T: Send> Send for SpscQueue<T>{}
+
+#[cfg(test)]
+mod tests{
+    use crate::spsc::{SpscQueue, new_spsc};
+    use crate::{SenderI, ReceiverI, WaitType, SendError, RecvError,
+                TrySendError, TryRecvError, SendTimeoutError, RecvTimeoutError};
+    use std::time::{Duration, Instant};
+    use std::thread;
+    use std::rc::Rc;
+
+    fn send(w : &dyn SenderI<i64>) {
+        for i in 0..10 {
+            w.send(i as i64).unwrap();
+            println!("send {}", i);
+        }
+    }
+    fn recv(r : &dyn ReceiverI<i64>) {
+        for _ in 0..10 {
+            let e = r.recv().unwrap();
+            println!("--recv {}", e);
+        }
+    }
+
+    #[test]
+    fn test1() {
+        // the queue itself, on one thread
+        let q = SpscQueue::<i64>::new(2<<5, WaitType::SleepWait);
+        for i in 0..10 {
+            q.push(i).unwrap();
+        }
+        assert_eq!(q.try_push(10), Ok(()));
+        assert_eq!((0..11).map(|_| q.pop().unwrap()).collect::<Vec<_>>(), (0..11).collect::<Vec<_>>());
+        assert_eq!(q.try_pop(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn test2(){
+        let (wr, rd) = new_spsc::<i64>(2<<6, WaitType::SleepWait);
+        let t1 = thread::spawn(move || {recv(&rd);});
+        let t2 = thread::spawn(move || {send(&wr);});
+        t1.join().unwrap();
+        t2.join().unwrap();
+    }
+
+    #[test]
+    fn test_disconnect() {
+        for wait_mode in [WaitType::BusyWait, WaitType::SleepWait, WaitType::adaptive()] {
+            // receiver drains what's left, then gets Disconnected
+            let (wr, rd) = new_spsc::<i64>(2<<6, wait_mode);
+            let t = thread::spawn(move || {send(&wr);});
+            recv(&rd);
+            t.join().unwrap();
+            assert_eq!(rd.recv(), Err(RecvError::Disconnected));
+        }
+
+        // a blocked receiver is woken up
+        let (wr, rd) = new_spsc::<i64>(4, WaitType::SleepWait);
+        let t = thread::spawn(move || rd.recv());
+        thread::sleep(std::time::Duration::from_millis(50));
+        drop(wr);
+        assert_eq!(t.join().unwrap(), Err(RecvError::Disconnected));
+
+        // a blocked sender is woken up and gets its element back
+        let (wr, rd) = new_spsc::<i64>(1, WaitType::SleepWait);
+        wr.send(1).unwrap();
+        let t = thread::spawn(move || wr.send(2));
+        thread::sleep(std::time::Duration::from_millis(50));
+        drop(rd);
+        assert_eq!(t.join().unwrap(), Err(SendError::Disconnected(2)));
+    }
+
+    #[test]
+    fn test_try() {
+        for wait_mode in [WaitType::BusyWait, WaitType::SleepWait, WaitType::adaptive()] {
+            let (wr, rd) = new_spsc::<i64>(2, wait_mode);
+            assert_eq!(rd.try_recv(), Err(TryRecvError::Empty));
+            wr.try_send(1).unwrap();
+            wr.try_send(2).unwrap();
+            assert_eq!(wr.try_send(3), Err(TrySendError::Full(3)));
+            assert_eq!(rd.try_recv(), Ok(1));
+            wr.try_send(3).unwrap();
+            drop(wr);
+            assert_eq!(rd.try_recv(), Ok(2));
+            assert_eq!(rd.try_recv(), Ok(3));
+            assert_eq!(rd.try_recv(), Err(TryRecvError::Disconnected));
+
+            let (wr, rd) = new_spsc::<i64>(2, WaitType::SleepWait);
+            drop(rd);
+            assert_eq!(wr.try_send(1), Err(TrySendError::Disconnected(1)));
+        }
+
+        // try_send wakes up a blocked receiver, try_recv a blocked sender
+        let (wr, rd) = new_spsc::<i64>(1, WaitType::SleepWait);
+        let t = thread::spawn(move || rd.recv());
+        thread::sleep(std::time::Duration::from_millis(50));
+        wr.try_send(1).unwrap();
+        assert_eq!(t.join().unwrap(), Ok(1));
+
+        let (wr, rd) = new_spsc::<i64>(1, WaitType::SleepWait);
+        wr.send(1).unwrap();
+        let t = thread::spawn(move || wr.send(2));
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(rd.try_recv(), Ok(1));
+        t.join().unwrap().unwrap();
+        assert_eq!(rd.recv(), Ok(2));
+    }
+
+    #[test]
+    fn test_capacity() {
+        for cap in [1, 3, 5, 1000] {
+            for wait_mode in [WaitType::BusyWait, WaitType::SleepWait, WaitType::adaptive()] {
+                // exactly cap elements fit, and come out in order
+                let (wr, rd) = new_spsc::<usize>(cap, wait_mode);
+                for round in 0..3 {
+                    for i in 0..cap {
+                        wr.try_send(round * cap + i).unwrap();
+                    }
+                    assert_eq!(wr.try_send(0), Err(TrySendError::Full(0)));
+                    for i in 0..cap {
+                        assert_eq!(rd.try_recv(), Ok(round * cap + i));
+                    }
+                    assert_eq!(rd.try_recv(), Err(TryRecvError::Empty));
+                }
+
+                // wrap around many times while both sides run
+                let n = cap * 7 + 3;
+                let t = thread::spawn(move || {
+                    for i in 0..n {
+                        wr.send(i).unwrap();
+                    }
+                });
+                for i in 0..n {
+                    assert_eq!(rd.recv(), Ok(i));
+                }
+                t.join().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_timeout() {
+        for wait_mode in [WaitType::BusyWait, WaitType::SleepWait, WaitType::adaptive()] {
+            let (wr, rd) = new_spsc::<i64>(1, wait_mode);
+            let begin = Instant::now();
+            assert_eq!(rd.recv_timeout(Duration::from_millis(20)), Err(RecvTimeoutError::Timeout));
+            assert!(begin.elapsed() >= Duration::from_millis(20));
+
+            wr.send_timeout(1, Duration::from_millis(20)).unwrap();
+            let begin = Instant::now();
+            assert_eq!(wr.send_timeout(2, Duration::from_millis(20)), Err(SendTimeoutError::Timeout(2)));
+            assert!(begin.elapsed() >= Duration::from_millis(20));
+            assert_eq!(wr.send_deadline(2, Instant::now()), Err(SendTimeoutError::Timeout(2)));
+
+            assert_eq!(rd.recv_deadline(Instant::now() + Duration::from_millis(20)), Ok(1));
+            assert_eq!(rd.recv_deadline(Instant::now()), Err(RecvTimeoutError::Timeout));
+
+            // a value sent in time is received
+            let t = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                wr.send(3).unwrap();
+                wr
+            });
+            assert_eq!(rd.recv_timeout(Duration::from_secs(10)), Ok(3));
+            drop(t.join().unwrap());
+            assert_eq!(rd.recv_timeout(Duration::from_secs(10)), Err(RecvTimeoutError::Disconnected));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity too large")]
+    fn test_capacity_overflow() {
+        // the buffer size overflows usize
+        let _ = new_spsc::<u64>(usize::MAX / 4, WaitType::SleepWait);
+    }
+
+    #[test]
+    fn test_zst() {
+        // a bounded semaphore
+        for &mode in &[WaitType::BusyWait, WaitType::SleepWait, WaitType::adaptive()] {
+            let (wr, rd) = new_spsc::<()>(3, mode);
+            for _ in 0..3 {
+                wr.try_send(()).unwrap();
+            }
+            assert_eq!(wr.try_send(()), Err(TrySendError::Full(())));
+            let t = thread::spawn(move || {
+                for _ in 0..1000 {
+                    wr.send(()).unwrap();
+                }
+            });
+            let mut n = 0;
+            while rd.recv().is_ok() {
+                n += 1;
+            }
+            t.join().unwrap();
+            assert_eq!(n, 1003);
+        }
+    }
+
+    #[test]
+    fn test_batch() {
+        for &mode in &[WaitType::BusyWait, WaitType::SleepWait, WaitType::adaptive()] {
+            for &cap in &[1, 7] {
+                let (wr, rd) = new_spsc::<usize>(cap, mode);
+                let n = 1000;
+                let t = thread::spawn(move || {
+                    for i in (0..n).step_by(10) {
+                        wr.send_batch(i..i + 10).unwrap();
+                    }
+                });
+                let mut v = Vec::new();
+                while let Ok(k) = rd.recv_batch(&mut v, 8) {
+                    assert!(k >= 1 && k <= 8.min(cap));
+                }
+                t.join().unwrap();
+                assert_eq!(v, (0..n).collect::<Vec<_>>());
+                assert_eq!(rd.recv_batch(&mut v, 0), Ok(0));
+            }
+
+            // elements not sent are returned
+            let (wr, rd) = new_spsc::<usize>(3, mode);
+            drop(rd);
+            assert_eq!(wr.send_batch(0..5), Err(SendError::Disconnected(vec![0, 1, 2, 3, 4])));
+        }
+    }
+
+    #[test]
+    fn test_adaptive() {
+        // park at once, snooze long
+        for &(spin_limit, yield_limit) in &[(0, 0), (3, 100)] {
+            let (wr, rd) = new_spsc::<usize>(2, WaitType::Adaptive { spin_limit, yield_limit });
+            let t = thread::spawn(move || {
+                for i in 0..1000 {
+                    wr.send(i).unwrap();
+                }
+            });
+            for i in 0..1000 {
+                assert_eq!(rd.recv(), Ok(i));
+            }
+            t.join().unwrap();
+            assert_eq!(rd.recv(), Err(RecvError::Disconnected));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_futex() {
+        for &cap in &[1, 5] {
+            let (wr, rd) = new_spsc::<usize>(cap, WaitType::Futex);
+            let t = thread::spawn(move || {
+                for i in 0..10000 {
+                    wr.send(i).unwrap();
+                }
+                wr.send_batch(0..100).unwrap();
+            });
+            for i in 0..10000 {
+                assert_eq!(rd.recv(), Ok(i));
+            }
+            let mut v = Vec::new();
+            while rd.recv_batch(&mut v, 7).is_ok() {}
+            t.join().unwrap();
+            assert_eq!(v, (0..100).collect::<Vec<_>>());
+        }
+
+        let (wr, rd) = new_spsc::<i64>(1, WaitType::Futex);
+        assert_eq!(rd.recv_timeout(Duration::from_millis(20)), Err(RecvTimeoutError::Timeout));
+        wr.send(1).unwrap();
+        assert_eq!(wr.send_timeout(2, Duration::from_millis(20)), Err(SendTimeoutError::Timeout(2)));
+        let t = thread::spawn(move || wr.send(2));
+        thread::sleep(Duration::from_millis(50));
+        drop(rd);
+        assert_eq!(t.join().unwrap(), Err(SendError::Disconnected(2)));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async() {
+        use crate::future::block_on;
+        for &mode in &[WaitType::BusyWait, WaitType::SleepWait] {
+            let (wr, rd) = new_spsc::<usize>(2, mode);
+            let t = thread::spawn(move || block_on(async {
+                for i in 0..1000 {
+                    wr.send_async(i).await.unwrap();
+                }
+            }));
+            block_on(async {
+                for i in 0..1000 {
+                    assert_eq!(rd.recv_async().await, Ok(i));
+                }
+                assert_eq!(rd.recv_async().await, Err(RecvError::Disconnected));
+            });
+            t.join().unwrap();
+        }
+
+        // async receiver, blocking sender
+        let (wr, rd) = new_spsc::<usize>(1, WaitType::SleepWait);
+        let t = thread::spawn(move || {
+            for i in 0..100 {
+                wr.send(i).unwrap();
+            }
+        });
+        block_on(async {
+            for i in 0..100 {
+                assert_eq!(rd.recv_async().await, Ok(i));
+            }
+        });
+        t.join().unwrap();
+
+        // disconnect wakes up a waiting task
+        let (wr, rd) = new_spsc::<usize>(1, WaitType::SleepWait);
+        wr.send(0).unwrap();
+        let t = thread::spawn(move || block_on(wr.send_async(1)));
+        thread::sleep(Duration::from_millis(50));
+        drop(rd);
+        assert_eq!(t.join().unwrap(), Err(SendError::Disconnected(1)));
+    }
+
+    #[test]
+    fn test_iter() {
+        let (wr, rd) = new_spsc::<usize>(8, WaitType::SleepWait);
+        for i in 0..5 {
+            wr.send(i).unwrap();
+        }
+        assert_eq!(rd.drain().collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+        assert_eq!(rd.try_iter().next(), None);
+
+        wr.send(5).unwrap();
+        wr.send(6).unwrap();
+        assert_eq!(rd.try_iter().collect::<Vec<_>>(), [5, 6]);
+
+        let t = thread::spawn(move || {
+            for i in 0..100 {
+                wr.send(i).unwrap();
+            }
+        });
+        let sum: usize = rd.iter().take(10).sum();
+        assert_eq!(sum, (0..10).sum());
+        // ends once the sender is gone
+        assert_eq!(rd.into_iter().collect::<Vec<_>>(), (10..100).collect::<Vec<_>>());
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_reserve() {
+        for &mode in &[WaitType::BusyWait, WaitType::SleepWait] {
+            let (mut wr, rd) = new_spsc::<[u64; 64]>(2, mode);
+            let t = thread::spawn(move || {
+                for i in 0..100 {
+                    let mut slot = wr.reserve().unwrap();
+                    let p = slot.as_mut_ptr() as *mut u64;
+                    for k in 0..64 {
+                        unsafe { p.add(k).write(i * 64 + k as u64) };
+                    }
+                    unsafe { slot.commit() };
+
+                    // dropped without commit, nothing is sent
+                    {
+                        let _slot = wr.reserve().unwrap();
+                    }
+                }
+                wr.reserve().unwrap().write([7; 64]);
+            });
+            for i in 0..100 {
+                let e = rd.recv().unwrap();
+                assert!(e.iter().enumerate().all(|(k, &x)| x == i * 64 + k as u64));
+            }
+            assert_eq!(rd.recv().unwrap(), [7; 64]);
+            t.join().unwrap();
+            assert!(rd.recv().is_err());
+        }
+
+        let (mut wr, rd) = new_spsc::<i64>(1, WaitType::SleepWait);
+        drop(rd);
+        assert!(matches!(wr.reserve(), Err(SendError::Disconnected(()))));
+    }
+
+    #[test]
+    fn test_reserve_leak() {
+        // an element built in a slot but not committed is never dropped
+        let rc = Rc::new(());
+        let (mut wr, rd) = new_spsc::<Rc<()>>(1, WaitType::SleepWait);
+        wr.reserve().unwrap().write(rc.clone());
+        assert_eq!(rd.recv().map(|e| Rc::ptr_eq(&e, &rc)), Ok(true));
+        assert_eq!(Rc::strong_count(&rc), 1);
+        {
+            let mut slot = wr.reserve().unwrap();
+            (*slot).write(rc.clone());
+        }
+        assert_eq!(Rc::strong_count(&rc), 2);
+        assert!(rd.is_empty());
+        // the next send overwrites it
+        wr.send(Rc::new(())).unwrap();
+        drop((wr, rd));
+        assert_eq!(Rc::strong_count(&rc), 2);
+    }
+
+    #[test]
+    fn test_recv_ref() {
+        let (wr, mut rd) = new_spsc::<String>(1, WaitType::SleepWait);
+        assert_eq!(rd.peek(), None);
+        let t = thread::spawn(move || {
+            for i in 0..100 {
+                wr.send(i.to_string()).unwrap();
+            }
+        });
+        for i in 0..100 {
+            let e = rd.recv_ref().unwrap();
+            assert_eq!(*e, i.to_string());
+            // the slot is still taken, the sender waits
+            thread::yield_now();
+            assert_eq!(e.len(), i.to_string().len());
+        }
+        t.join().unwrap();
+        assert!(rd.recv_ref().is_err());
+
+        let (wr, mut rd) = new_spsc::<String>(4, WaitType::BusyWait);
+        wr.send("a".to_string()).unwrap();
+        wr.send("b".to_string()).unwrap();
+        assert_eq!(rd.peek().map(|e| e.as_str()), Some("a"));
+        assert_eq!(rd.peek().map(|e| e.as_str()), Some("a"));
+        assert_eq!(rd.recv().unwrap(), "a");
+        assert_eq!(&*rd.recv_ref().unwrap(), "b");
+        assert_eq!(rd.peek(), None);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn test_stats() {
+        let (wr, rd) = new_spsc::<i64>(2, WaitType::SleepWait);
+        let t = thread::spawn(move || {
+            // the queue is empty, the receiver waits
+            let v: Vec<i64> = rd.iter().collect();
+            (v, rd.stats())
+        });
+        thread::sleep(Duration::from_millis(50));
+        wr.send_batch(0..2).unwrap();
+        for i in 2..10 {
+            wr.send(i).unwrap();
+        }
+        let s = wr.stats();
+        assert_eq!(s.sends, 10);
+        assert_eq!(s.peak_len, 2);
+        assert_eq!(s.waiting_senders, 0);
+        drop(wr);
+        let (v, s) = t.join().unwrap();
+        assert_eq!(v, (0..10).collect::<Vec<_>>());
+        assert_eq!(s.recvs, 10);
+        assert!(s.recv_waits >= 1);
+        assert_eq!(s.waiting_receivers, 0);
+
+        let (wr, rd) = new_spsc::<i64>(1, WaitType::BusyWait);
+        wr.send(1).unwrap();
+        assert_eq!(wr.send_timeout(2, Duration::from_millis(5)), Err(SendTimeoutError::Timeout(2)));
+        assert!(rd.stats().spins > 0);
+        assert_eq!(rd.stats().send_waits, 0);
+    }
+
+    #[test]
+    fn test_len() {
+        let (wr, rd) = new_spsc::<i64>(3, WaitType::SleepWait);
+        assert!(wr.is_empty() && !wr.is_full());
+        assert_eq!((wr.len(), wr.capacity()), (0, 3));
+        wr.send_batch(0..3).unwrap();
+        assert!(rd.is_full());
+        assert_eq!(rd.len(), 3);
+        rd.recv().unwrap();
+        assert_eq!((wr.len(), rd.len()), (2, 2));
+        assert!(!rd.is_disconnected());
+        drop(wr);
+        assert!(rd.is_disconnected());
+        assert_eq!(rd.drain().count(), 2);
+        assert!(rd.is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+//!
+//! counters of a queue, with the `stats` feature
+//!
+//! `SpscQueue` and `MpmcQueue` count what their senders and receivers do, and
+//! `stats()` returns a snapshot. The counters are relaxed atomics apart from
+//! the queue's own state, so a snapshot taken while the queue is in use is not
+//! consistent: e.g. `recvs` may already count an element `sends` does not.
+//!
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Snapshot of the counters of a queue.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// elements sent
+    pub sends: u64,
+    /// elements received
+    pub recvs: u64,
+    /// times a sender blocked on `sem_room` (or the futex) until there was room
+    pub send_waits: u64,
+    /// times a receiver blocked on `sem_elem` (or the futex) until there was an element
+    pub recv_waits: u64,
+    /// rounds of `BusyWait` polling and of the `Adaptive` spin and yield phase
+    pub spins: u64,
+    /// senders blocked now
+    pub waiting_senders: u64,
+    /// receivers blocked now
+    pub waiting_receivers: u64,
+    /// most elements in the queue at once
+    pub peak_len: u64,
+}
+
+pub(crate) struct StatCounters {
+    sends: AtomicU64,
+    recvs: AtomicU64,
+    send_waits: AtomicU64,
+    recv_waits: AtomicU64,
+    spins: AtomicU64,
+    waiting_senders: AtomicU64,
+    waiting_receivers: AtomicU64,
+    peak_len: AtomicU64,
+}
+
+impl StatCounters {
+    pub(crate) fn new() -> StatCounters {
+        StatCounters {
+            sends: AtomicU64::new(0),
+            recvs: AtomicU64::new(0),
+            send_waits: AtomicU64::new(0),
+            recv_waits: AtomicU64::new(0),
+            spins: AtomicU64::new(0),
+            waiting_senders: AtomicU64::new(0),
+            waiting_receivers: AtomicU64::new(0),
+            peak_len: AtomicU64::new(0),
+        }
+    }
+
+    /// `n` elements were sent, leaving `len` in the queue.
+    #[inline]
+    pub(crate) fn sent(&self, n: usize, len: usize) {
+        self.sends.fetch_add(n as u64, Ordering::Relaxed);
+        self.peak_len.fetch_max(len as u64, Ordering::Relaxed);
+    }
+    #[inline]
+    pub(crate) fn received(&self, n: usize) {
+        self.recvs.fetch_add(n as u64, Ordering::Relaxed);
+    }
+    #[inline]
+    pub(crate) fn spin(&self) {
+        self.spins.fetch_add(1, Ordering::Relaxed);
+    }
+    /// A sender blocks until the guard is dropped.
+    pub(crate) fn send_wait(&self) -> WaitGuard<'_> {
+        self.send_waits.fetch_add(1, Ordering::Relaxed);
+        WaitGuard::new(&self.waiting_senders)
+    }
+    /// A receiver blocks until the guard is dropped.
+    pub(crate) fn recv_wait(&self) -> WaitGuard<'_> {
+        self.recv_waits.fetch_add(1, Ordering::Relaxed);
+        WaitGuard::new(&self.waiting_receivers)
+    }
+
+    pub(crate) fn snapshot(&self) -> Stats {
+        Stats {
+            sends: self.sends.load(Ordering::Relaxed),
+            recvs: self.recvs.load(Ordering::Relaxed),
+            send_waits: self.send_waits.load(Ordering::Relaxed),
+            recv_waits: self.recv_waits.load(Ordering::Relaxed),
+            spins: self.spins.load(Ordering::Relaxed),
+            waiting_senders: self.waiting_senders.load(Ordering::Relaxed),
+            waiting_receivers: self.waiting_receivers.load(Ordering::Relaxed),
+            peak_len: self.peak_len.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counts a blocked thread in `waiting_senders` or `waiting_receivers`.
+pub(crate) struct WaitGuard<'a>(&'a AtomicU64);
+impl<'a> WaitGuard<'a> {
+    fn new(waiting: &'a AtomicU64) -> Self {
+        waiting.fetch_add(1, Ordering::Relaxed);
+        WaitGuard(waiting)
+    }
+}
+impl Drop for WaitGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
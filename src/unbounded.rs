@@ -0,0 +1,471 @@
+//!
+//! rust unbounded mpmc queue, linked fixed-size blocks
+//!
+//! Senders append to the tail block under the `tail` lock and link a new
+//! block when it is full. Receivers take from the head block under the
+//! `head` lock and free it once all of its slots are consumed. As in
+//! `MpmcQueue`, the atomic `count` tells receivers whether there is an element.
+//!
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::{Mutex, MutexGuard, Condvar, Arc};
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::{ptr, time::Instant};
+use crate::{SenderI, ReceiverI, SendError, RecvError,
+            TrySendError, TryRecvError, SendTimeoutError, RecvTimeoutError};
+use crate::iter::{Iter, TryIter, Drain, IntoIter};
+
+pub struct Sender<T> {
+    inner : Arc<UnboundedQueue<T>>,
+}
+impl<T> SenderI<T> for Sender<T> {
+    fn send(&self, e: T) -> Result<(), SendError<T>> {
+        self.inner.push(e)
+    }
+    /// Never fails with `Full`.
+    fn try_send(&self, e: T) -> Result<(), TrySendError<T>> {
+        self.inner.push(e).map_err(|SendError::Disconnected(e)| TrySendError::Disconnected(e))
+    }
+    /// Never waits, so never times out.
+    fn send_deadline(&self, e: T, _deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        self.inner.push(e).map_err(SendTimeoutError::from)
+    }
+}
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.inner.senders.fetch_add(1, Ordering::SeqCst);
+        Sender { inner: self.inner.clone() }
+    }
+}
+impl<T> Sender<T> {
+    /// See `UnboundedQueue::len`.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+    /// See `UnboundedQueue::is_empty`.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+    /// See `UnboundedQueue::is_full`.
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+    /// See `UnboundedQueue::capacity`.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See `UnboundedQueue::is_disconnected`.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.is_disconnected()
+    }
+}
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.inner.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.disconnect();
+        }
+    }
+}
+pub struct Receiver<T> {
+    inner: Arc<UnboundedQueue<T>>,
+}
+impl<T> ReceiverI<T> for Receiver<T> {
+    fn recv(&self) -> Result<T, RecvError> {
+        self.inner.pop()
+    }
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.inner.try_pop()
+    }
+    fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.inner.pop_until(Some(deadline))
+    }
+}
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.inner.receivers.fetch_add(1, Ordering::SeqCst);
+        Receiver { inner: self.inner.clone() }
+    }
+}
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if self.inner.receivers.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.disconnect();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// See `UnboundedQueue::len`.
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+    /// See `UnboundedQueue::is_empty`.
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+    /// See `UnboundedQueue::is_full`.
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+    /// See `UnboundedQueue::capacity`.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See `UnboundedQueue::is_disconnected`.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.is_disconnected()
+    }
+    /// Iterate over the elements, waiting for each one, until the queue is
+    /// disconnected and drained.
+    pub fn iter(&self) -> Iter<'_, T, Self> {
+        Iter::new(self)
+    }
+    /// Iterate over the elements available without waiting.
+    pub fn try_iter(&self) -> TryIter<'_, T, Self> {
+        TryIter::new(self)
+    }
+    /// Iterate over at most the elements in the queue now.
+    pub fn drain(&self) -> Drain<'_, T, Self> {
+        Drain::new(self, self.inner.len())
+    }
+}
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T, Self>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::new(self)
+    }
+}
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T, Receiver<T>>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub fn new_unbounded<T>() -> (Sender<T>, Receiver<T>) {
+    let qs = Arc::new(UnboundedQueue::<T>::new());
+    qs.senders.store(1, Ordering::SeqCst);
+    qs.receivers.store(1, Ordering::SeqCst);
+    let qr = qs.clone();
+    (Sender { inner: qs }, Receiver { inner: qr })
+}
+
+/// Number of elements in one block.
+const BLOCK_CAP : usize = 32;
+
+struct Block<T> {
+    slots: [UnsafeCell<MaybeUninit<T>>; BLOCK_CAP],
+    next: AtomicPtr<Block<T>>,
+}
+impl<T> Block<T> {
+    fn alloc() -> *mut Block<T> {
+        Box::into_raw(Box::new(Block {
+            slots: std::array::from_fn(|_| UnsafeCell::new(MaybeUninit::uninit())),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }))
+    }
+}
+
+/// Next slot to write (tail) or to read (head).
+struct Position<T> {
+    block: *mut Block<T>,
+    idx: usize,
+}
+
+pub struct UnboundedQueue<T> {
+    count: AtomicUsize,
+    _pad1: [i64; 7],
+    tail: Mutex<Position<T>>,
+    _pad2: [i64; 7],
+    head: Mutex<Position<T>>,
+    _pad3: [i64; 7],
+    sem_elem: Condvar,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    disconnected: AtomicBool,
+}
+
+impl<T> UnboundedQueue<T> {
+    pub fn new() -> UnboundedQueue<T> {
+        let block = Block::<T>::alloc();
+        UnboundedQueue {
+            count: AtomicUsize::new(0),
+            _pad1: [0; 7],
+            tail: Mutex::new(Position { block, idx: 0 }),
+            _pad2: [0; 7],
+            head: Mutex::new(Position { block, idx: 0 }),
+            _pad3: [0; 7],
+            sem_elem: Default::default(),
+            senders: AtomicUsize::new(0),
+            receivers: AtomicUsize::new(0),
+            disconnected: AtomicBool::new(false),
+        }
+    }
+
+    /// Called when the last Sender or the last Receiver is dropped: wake up
+    /// all waiting receivers, which then fail once the queue is drained.
+    fn disconnect(&self) {
+        self.disconnected.store(true, Ordering::SeqCst);
+        let _g = self.head.lock().unwrap();
+        self.sem_elem.notify_all();
+    }
+    /// Number of elements in the queue, see the crate docs on how current it is.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Always false, there is no bound.
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        false
+    }
+    /// `usize::MAX`, there is no bound.
+    pub fn capacity(&self) -> usize {
+        usize::MAX
+    }
+    /// All Senders or all Receivers are gone. Stays true once true,
+    /// elements sent before can still be received.
+    #[inline]
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected.load(Ordering::SeqCst)
+    }
+
+    /// Append an element, never waits.
+    pub fn push(&self, e: T) -> Result<(), SendError<T>> {
+        if self.is_disconnected() {
+            return Err(SendError::Disconnected(e));
+        }
+        {
+            let mut tail = self.tail.lock().unwrap();
+            if tail.idx == BLOCK_CAP {
+                // the tail block is full, link a new one
+                let next = Block::<T>::alloc();
+                unsafe { (*tail.block).next.store(next, Ordering::SeqCst) };
+                tail.block = next;
+                tail.idx = 0;
+            }
+            unsafe {
+                (*(*tail.block).slots[tail.idx].get()).write(e);
+            }
+            tail.idx += 1;
+        }
+
+        let c = self.count.fetch_add(1, Ordering::SeqCst);
+        if c == 0 {
+            let _g = self.head.lock().unwrap();
+            self.sem_elem.notify_one();
+        }
+        Ok(())
+    }
+
+    pub fn pop(&self) -> Result<T, RecvError> {
+        self.pop_until(None).map_err(|_| RecvError::Disconnected)
+    }
+    /// Pop, giving up at `deadline` if there is one.
+    fn pop_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let mut g = self.head.lock().unwrap();
+        while self.count.load(Ordering::SeqCst) == 0 {
+            // senders are gone, checked after count so nothing sent is lost
+            if self.is_disconnected() && self.count.load(Ordering::SeqCst) == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            g = match deadline {
+                None => self.sem_elem.wait(g).unwrap(),
+                Some(d) => {
+                    let now = Instant::now();
+                    if now >= d {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    self.sem_elem.wait_timeout(g, d - now).unwrap().0
+                }
+            };
+        }
+        Ok(self.pop_locked(g))
+    }
+    /// Take an element while holding the `head` lock, the queue is not empty.
+    /// Wake up the next receiver if there are still elements.
+    fn pop_locked(&self, mut head: MutexGuard<'_, Position<T>>) -> T {
+        if head.idx == BLOCK_CAP {
+            // the head block is consumed, the sender has linked the next one
+            // before publishing the element we are going to take
+            let old = head.block;
+            head.block = unsafe { (*old).next.load(Ordering::SeqCst) };
+            head.idx = 0;
+            drop(unsafe { Box::from_raw(old) });
+        }
+        let e = unsafe { (*(*head.block).slots[head.idx].get()).assume_init_read() };
+        head.idx += 1;
+
+        let c = self.count.fetch_sub(1, Ordering::SeqCst);
+        if c-1 > 0 {
+            self.sem_elem.notify_one();
+        }
+        e
+    }
+
+    pub fn try_pop(&self) -> Result<T, TryRecvError> {
+        let g = self.head.lock().unwrap();
+        if self.count.load(Ordering::SeqCst) == 0 {
+            if self.is_disconnected() && self.count.load(Ordering::SeqCst) == 0 {
+                return Err(TryRecvError::Disconnected);
+            }
+            return Err(TryRecvError::Empty);
+        }
+        Ok(self.pop_locked(g))
+    }
+}
+impl<T> Default for UnboundedQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T> Drop for UnboundedQueue<T> {
+    fn drop(&mut self) {
+        // remove all elements
+        while *self.count.get_mut() > 0 {
+            let g = self.head.lock().unwrap();
+            let _ = self.pop_locked(g);
+        }
+
+        // free the remaining block
+        let head = self.head.get_mut().unwrap();
+        drop(unsafe { Box::from_raw(head.block) });
+    }
+}
+unsafe impl<T: Send> Send for UnboundedQueue<T>{}
+unsafe impl<T: Send> Sync for UnboundedQueue<T>{}
+
+impl<T> SenderI<T> for UnboundedQueue<T> {
+    fn send(&self, e: T) -> Result<(), SendError<T>> {
+        self.push(e)
+    }
+    fn try_send(&self, e: T) -> Result<(), TrySendError<T>> {
+        self.push(e).map_err(|SendError::Disconnected(e)| TrySendError::Disconnected(e))
+    }
+    fn send_deadline(&self, e: T, _deadline: Instant) -> Result<(), SendTimeoutError<T>> {
+        self.push(e).map_err(SendTimeoutError::from)
+    }
+}
+impl<T> ReceiverI<T> for UnboundedQueue<T> {
+    fn recv(&self) -> Result<T, RecvError> {
+        self.pop()
+    }
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.try_pop()
+    }
+    fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.pop_until(Some(deadline))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::unbounded::{UnboundedQueue, new_unbounded, BLOCK_CAP};
+    use crate::{SenderI, ReceiverI, SendError, RecvError, TryRecvError, RecvTimeoutError};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test1() {
+        // never full, across many blocks
+        let q = UnboundedQueue::<usize>::new();
+        let n = BLOCK_CAP * 10 + 5;
+        for i in 0..n {
+            q.send(i).unwrap();
+        }
+        for i in 0..n {
+            assert_eq!(q.recv(), Ok(i));
+        }
+        assert_eq!(q.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn test2() {
+        let (wr, rd) = new_unbounded::<usize>();
+        let ts: Vec<_> = (0..4).map(|_| {
+            let wr = wr.clone();
+            thread::spawn(move || {
+                for i in 0..10000 {
+                    wr.send(i).unwrap();
+                }
+            })
+        }).collect();
+        drop(wr);
+        let rs: Vec<_> = (0..4).map(|_| {
+            let rd = rd.clone();
+            thread::spawn(move || {
+                let mut sum = 0;
+                while let Ok(e) = rd.recv() {
+                    sum += e;
+                }
+                sum
+            })
+        }).collect();
+        for t in ts {
+            t.join().unwrap();
+        }
+        let sum: usize = rs.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(sum, 4 * (0..10000).sum::<usize>());
+        assert_eq!(rd.recv(), Err(RecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (wr, rd) = new_unbounded::<i64>();
+        assert_eq!(rd.recv_timeout(Duration::from_millis(20)), Err(RecvTimeoutError::Timeout));
+        let t = thread::spawn(move || rd.recv());
+        thread::sleep(Duration::from_millis(50));
+        drop(wr);
+        assert_eq!(t.join().unwrap(), Err(RecvError::Disconnected));
+
+        let (wr, rd) = new_unbounded::<i64>();
+        drop(rd);
+        assert_eq!(wr.send(1), Err(SendError::Disconnected(1)));
+    }
+
+    #[test]
+    fn test_drop() {
+        // elements left in the queue are dropped with it
+        struct Counted(Arc<AtomicUsize>);
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        let drops = Arc::new(AtomicUsize::new(0));
+        let (wr, rd) = new_unbounded::<Counted>();
+        for _ in 0..BLOCK_CAP * 3 {
+            assert!(wr.send(Counted(drops.clone())).is_ok());
+        }
+        for _ in 0..BLOCK_CAP + 1 {
+            assert!(rd.recv().is_ok());
+        }
+        drop(wr);
+        drop(rd);
+        assert_eq!(drops.load(Ordering::SeqCst), BLOCK_CAP * 3);
+    }
+
+    #[test]
+    fn test_len() {
+        let (wr, rd) = new_unbounded::<usize>();
+        for i in 0..BLOCK_CAP + 1 {
+            wr.send(i).unwrap();
+        }
+        assert_eq!(rd.len(), BLOCK_CAP + 1);
+        assert!(!wr.is_full());
+        assert_eq!(wr.capacity(), usize::MAX);
+        drop(wr);
+        assert!(rd.is_disconnected());
+        assert_eq!(rd.drain().count(), BLOCK_CAP + 1);
+        assert!(rd.is_empty());
+    }
+}
@@ -0,0 +1,65 @@
+//!
+//! wakers of tasks and threads waiting on a queue
+//!
+//! Used by the async futures and by `Select`. The queue wakes all registered
+//! wakers when it leaves the full or the empty state, and when it is
+//! disconnected.
+//!
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Wake, Waker};
+use std::thread::{self, Thread};
+
+/// Tasks or threads waiting for room or for elements.
+pub(crate) struct WakerSet {
+    wakers: Mutex<Vec<Waker>>,
+    /// lets the queue skip the lock when nobody waits
+    empty: AtomicBool,
+}
+
+impl WakerSet {
+    pub(crate) fn new() -> WakerSet {
+        WakerSet { wakers: Mutex::new(Vec::new()), empty: AtomicBool::new(true) }
+    }
+    pub(crate) fn register(&self, waker: &Waker) {
+        let mut g = self.wakers.lock().unwrap();
+        if !g.iter().any(|w| w.will_wake(waker)) {
+            g.push(waker.clone());
+        }
+        self.empty.store(false, Ordering::SeqCst);
+    }
+    pub(crate) fn wake_all(&self) {
+        if self.empty.load(Ordering::SeqCst) {
+            return;
+        }
+        let wakers = {
+            let mut g = self.wakers.lock().unwrap();
+            self.empty.store(true, Ordering::SeqCst);
+            std::mem::take(&mut *g)
+        };
+        for w in wakers {
+            w.wake();
+        }
+    }
+}
+
+struct ThreadWaker(Thread);
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+thread_local! {
+    static THREAD_WAKER: Waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+}
+
+/// Waker unparking the current thread. The same one on each call, so
+/// `WakerSet` keeps only one per thread.
+pub(crate) fn thread_waker() -> Waker {
+    THREAD_WAKER.with(|w| w.clone())
+}
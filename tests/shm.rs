@@ -0,0 +1,78 @@
+//!
+//! ShmSpsc between forked processes. Without the libtest harness: `fork`
+//! must run in a process with a single thread, so the tests run one by one
+//! on the main thread.
+//!
+
+#[cfg(target_os = "linux")]
+mod shm {
+    use queue::shm::ShmSpsc;
+    use queue::{SenderI, ReceiverI, WaitType, RecvError};
+    use std::panic::{self, AssertUnwindSafe};
+
+    /// Run `child` in a forked process, which exits with 0 if it returned
+    /// true. Returns the child's pid.
+    fn fork(child: impl FnOnce() -> bool) -> libc::pid_t {
+        let pid = unsafe { libc::fork() };
+        assert!(pid != -1, "fork failed");
+        if pid == 0 {
+            let ok = panic::catch_unwind(AssertUnwindSafe(child)).unwrap_or(false);
+            unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+        }
+        pid
+    }
+    fn wait_ok(pid: libc::pid_t) {
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0, "child failed: {}", status);
+    }
+
+    fn test_memfd() {
+        let q = ShmSpsc::<u64>::memfd(4, WaitType::SleepWait).unwrap();
+        let pid = fork(|| {
+            let r = q.receiver().unwrap();
+            (0..1000).all(|i| r.recv() == Ok(i)) && r.recv() == Err(RecvError::Disconnected)
+        });
+        let w = q.sender().unwrap();
+        for i in 0..1000 {
+            w.send(i).unwrap();
+        }
+        drop(w);
+        wait_ok(pid);
+    }
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    struct Point {
+        x: f64,
+        tag: u8,
+    }
+
+    fn test_named() {
+        let name = format!("/queue-test-{}", std::process::id());
+        let q = ShmSpsc::<Point>::create(&name, 3, WaitType::adaptive()).unwrap();
+        let r = q.receiver().unwrap();
+        let pid = fork(|| {
+            let q = ShmSpsc::<Point>::open(&name, WaitType::BusyWait).unwrap();
+            let w = q.sender().unwrap();
+            (0..100).all(|i| w.send(Point { x: i as f64, tag: i as u8 }).is_ok())
+        });
+        let v: Vec<Point> = r.iter().collect();
+        wait_ok(pid);
+        ShmSpsc::<Point>::unlink(&name).unwrap();
+        assert_eq!(v.len(), 100);
+        assert!(v.iter().enumerate().all(|(i, p)| *p == Point { x: i as f64, tag: i as u8 }));
+    }
+
+    pub fn run() {
+        let tests: [(&str, fn()); 2] = [("test_memfd", test_memfd), ("test_named", test_named)];
+        for (name, test) in tests.iter() {
+            test();
+            println!("test shm::{} ... ok", name);
+        }
+    }
+}
+
+fn main() {
+    #[cfg(target_os = "linux")]
+    shm::run();
+}